@@ -2,9 +2,8 @@ extern crate nalgebra_glm as glm;
 use gl::types::*;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::{mem, os::raw::c_void, ptr, str};
+use std::{ptr, str};
 
-mod shader;
 mod util;
 
 use glutin::event::{
@@ -15,59 +14,16 @@ use glutin::event::{
 };
 use glutin::event_loop::ControlFlow;
 
+use gloom_common::{shader, vertex};
+
 const SCREEN_W: u32 = 600;
 const SCREEN_H: u32 = 600;
 
-// Helper functions to make interacting with OpenGL a little bit prettier. You will need these!
-// The names should be pretty self explanatory
-fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
-}
-
-// Get the OpenGL-compatible pointer to an arbitrary array of numbers
-fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
-}
-
-// Get the size of the given type in bytes
-fn size_of<T>() -> i32 {
-    mem::size_of::<T>() as i32
-}
-
-// Get an offset in bytes for n units of type T
-fn offset<T>(n: u32) -> *const c_void {
-    (n * mem::size_of::<T>() as u32) as *const T as *const c_void
-}
-
 //==============TASK 1a==============
-unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>) -> u32 {
-    let mut array_id: u32 = 0;
-    gl::GenVertexArrays(1, &mut array_id);
-    gl::BindVertexArray(array_id);
-
-    let mut vertex_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut vertex_buffer_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertices),
-        pointer_to_array(vertices),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-    gl::EnableVertexAttribArray(0);
-
-    let mut index_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut index_buffer_id);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer_id);
-    gl::BufferData(
-        gl::ELEMENT_ARRAY_BUFFER,
-        byte_size_of_array(indices),
-        pointer_to_array(indices),
-        gl::STATIC_DRAW,
-    );
-
-    return array_id;
+unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>) -> vertex::Vao {
+    vertex::VaoBuilder::new(indices)
+        .attribute(vertex::Attribute::Position, vertices)
+        .build()
 }
 
 fn main() {
@@ -130,7 +86,7 @@ fn main() {
            0, 10, 9,  
            0, 12, 11, 
         ];
-        let task1_vao: u32 = unsafe { create_vao(&task1_vertices, &task1_indices) };
+        let task1_vao: vertex::Vao = unsafe { create_vao(&task1_vertices, &task1_indices) };
 
         //==============TASK 2a==============
         let task2a_vertices: Vec<f32> = vec![
@@ -141,7 +97,7 @@ fn main() {
         let task2a_indices: Vec<u32> = vec![
             0, 1, 2,
         ];
-        let task2a_vao: u32 = unsafe { create_vao(&task2a_vertices, &task2a_indices) };
+        let task2a_vao: vertex::Vao = unsafe { create_vao(&task2a_vertices, &task2a_indices) };
 
         //==============TASK 2b==============
         let task2b_vertices: Vec<f32> = vec![
@@ -156,7 +112,7 @@ fn main() {
             //Clockwise
             //0, 2, 1,
         ];
-        let task2b_vao: u32 = unsafe { create_vao(&task2b_vertices, &task2b_indices) };
+        let task2b_vao: vertex::Vao = unsafe { create_vao(&task2b_vertices, &task2b_indices) };
 
         //==============TASK 2d==============
         let task2d_vertices: Vec<f32> = vec![
@@ -167,18 +123,19 @@ fn main() {
         let task2d_indices: Vec<u32> = vec![
             0, 1, 2,
         ];
-        let task2d_vao: u32 = unsafe { create_vao(&task2d_vertices, &task2d_indices) };
+        let task2d_vao: vertex::Vao = unsafe { create_vao(&task2d_vertices, &task2d_indices) };
 
         //==============TASK 1b==============
-        let shader = unsafe {
+        let mut shader = unsafe {
             shader::ShaderBuilder::new()
                 .attach_file("./shaders/simple.vert")
-                .attach_file("./shaders/simple.frag")
-                .link()
+                .and_then(|builder| builder.attach_file("./shaders/simple.frag"))
+                .and_then(|builder| builder.link())
+                .unwrap_or_else(|e| panic!("{}", e))
         };
 
         unsafe {
-            gl::UseProgram(shader.program_id);
+            shader.activate();
         }
 
         // Used to demonstrate keyboard handling -- feel free to remove
@@ -193,6 +150,13 @@ fn main() {
             let delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
 
+            // Pick up changes to the shader files, keeping the old program if the new one doesn't build
+            match unsafe { shader.reload_if_changed() } {
+                Ok(true) => unsafe { shader.activate() },
+                Ok(false) => {}
+                Err(e) => println!("{}", e),
+            }
+
             // Handle keyboard input
             if let Ok(keys) = pressed_keys.lock() {
                 for key in keys.iter() {
@@ -216,19 +180,19 @@ fn main() {
                 // Issue the necessary commands to draw your scene here
 
                 //==============TASK 1c==============
-                //gl::BindVertexArray(task1_vao);
+                //task1_vao.bind();
                 //gl::DrawElements(gl::TRIANGLES, 18, gl::UNSIGNED_INT, ptr::null());
 
                 //==============TASK 2a==============
-                //gl::BindVertexArray(task2a_vao);
+                //task2a_vao.bind();
                 //gl::DrawElements(gl::LINE_LOOP, 3, gl::UNSIGNED_INT, ptr::null());
 
                 //==============TASK 2b==============
-                gl::BindVertexArray(task2b_vao);
+                task2b_vao.bind();
                 gl::DrawElements(gl::TRIANGLES, 3, gl::UNSIGNED_INT, ptr::null());
 
                 //==============TASK 2d==============
-                //gl::BindVertexArray(task2d_vao);
+                //task2d_vao.bind();
                 //gl::DrawElements(gl::TRIANGLES, 3, gl::UNSIGNED_INT, ptr::null());
             }
 
@@ -2,9 +2,8 @@ extern crate nalgebra_glm as glm;
 use gl::types::*;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::{mem, os::raw::c_void, ptr, str, ffi::CString};
+use std::{ptr, str};
 
-mod shader;
 mod util;
 
 use glutin::event::{
@@ -15,71 +14,17 @@ use glutin::event::{
 };
 use glutin::event_loop::ControlFlow;
 
+use gloom_common::{shader, vertex};
+
 const SCREEN_W: u32 = 600;
 const SCREEN_H: u32 = 600;
 
-// Helper functions to make interacting with OpenGL a little bit prettier. You will need these!
-// The names should be pretty self explanatory
-fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
-}
-
-// Get the OpenGL-compatible pointer to an arbitrary array of numbers
-fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
-}
-
-// Get the size of the given type in bytes
-fn size_of<T>() -> i32 {
-    mem::size_of::<T>() as i32
-}
-
-// Get an offset in bytes for n units of type T
-fn offset<T>(n: u32) -> *const c_void {
-    (n * mem::size_of::<T>() as u32) as *const T as *const c_void
-}
-
 //==============TASK 1ai==============
-unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, vertex_colors: &Vec<f32>) -> u32 {
-    let mut array_id: u32 = 0;
-    gl::GenVertexArrays(1, &mut array_id);
-    gl::BindVertexArray(array_id);
-
-    let mut vertex_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut vertex_buffer_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertices),
-        pointer_to_array(vertices),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-    gl::EnableVertexAttribArray(0);
-
-    let mut index_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut index_buffer_id);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer_id);
-    gl::BufferData(
-        gl::ELEMENT_ARRAY_BUFFER,
-        byte_size_of_array(indices),
-        pointer_to_array(indices),
-        gl::STATIC_DRAW,
-    );
-
-    let mut vertex_color_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut vertex_color_buffer_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_color_buffer_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertex_colors),
-        pointer_to_array(vertex_colors),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-    gl::EnableVertexAttribArray(1);
-
-    return array_id;
+unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, vertex_colors: &Vec<f32>) -> vertex::Vao {
+    vertex::VaoBuilder::new(indices)
+        .attribute(vertex::Attribute::Position, vertices)
+        .attribute(vertex::Attribute::Color, vertex_colors)
+        .build()
 }
 
 fn main() {
@@ -158,7 +103,7 @@ fn main() {
             0.0, 1.0, 0.0, 1.0, 
             0.0, 0.0, 1.0, 1.0, 
         ];
-        let task1_vao: u32 = unsafe { create_vao(&task1_vertices, &task1_indices, &task1_color_vertices) };
+        let task1_vao: vertex::Vao = unsafe { create_vao(&task1_vertices, &task1_indices, &task1_color_vertices) };
 
         //==============TASK 2==============
         let task2_vertices: Vec<f32> = vec![
@@ -193,7 +138,7 @@ fn main() {
             0.0, 1.0, 0.0, 0.33,
             0.0, 1.0, 0.0, 0.33,
         ];
-        let task2_vao: u32 = unsafe { create_vao(&task2_vertices, &task2_indices, &task2_color_vertices) };
+        let task2_vao: vertex::Vao = unsafe { create_vao(&task2_vertices, &task2_indices, &task2_color_vertices) };
 
         //==============TASK 3==============
         let task3_vertices: Vec<f32> = vec![
@@ -210,17 +155,18 @@ fn main() {
             0.0, 1.0, 0.0, 1.0,
             0.0, 0.0, 1.0, 1.0,
         ];
-        let task3_vao: u32 = unsafe { create_vao(&task3_vertices, &task3_indices, &task3_color_vertices) };
+        let task3_vao: vertex::Vao = unsafe { create_vao(&task3_vertices, &task3_indices, &task3_color_vertices) };
         
-        let shader = unsafe {
+        let mut shader = unsafe {
             shader::ShaderBuilder::new()
                 .attach_file("./shaders/simple.vert")
-                .attach_file("./shaders/simple.frag")
-                .link()
+                .and_then(|builder| builder.attach_file("./shaders/simple.frag"))
+                .and_then(|builder| builder.link())
+                .unwrap_or_else(|e| panic!("{}", e))
         };
 
         unsafe {
-            gl::UseProgram(shader.program_id);
+            shader.activate();
         }
 
 
@@ -238,6 +184,13 @@ fn main() {
             let delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
 
+            // Pick up changes to the shader files, keeping the old program if the new one doesn't build
+            match unsafe { shader.reload_if_changed() } {
+                Ok(true) => unsafe { shader.activate() },
+                Ok(false) => {}
+                Err(e) => println!("{}", e),
+            }
+
             
 
             // Handle keyboard input
@@ -286,8 +239,7 @@ fn main() {
 
             unsafe {
                 //TASK 3
-                //let value = gl::GetUniformLocation(shader.program_id, CString::new("value").expect("Convert to c-string").as_ptr());
-                //gl::Uniform1f(value, elapsed.sin());
+                //shader.set_f32("value", elapsed.sin());
                 
                 //TASK 4
                 let translate: glm::Mat4 = glm::mat4(
@@ -310,8 +262,7 @@ fn main() {
                 );
                 let perspective_transform: glm::Mat4 = glm::perspective(1.0, 1.0, 1.0, 100.0);
 
-                let transformation = gl::GetUniformLocation(shader.program_id, CString::new("transformation").expect("Convert to c-string").as_ptr());
-                gl::UniformMatrix4fv(transformation, 1, 0, (perspective_transform * rotatex * rotatey * translate).as_ptr());
+                shader.set_mat4("transformation", &(perspective_transform * rotatex * rotatey * translate));
 
 
                 gl::ClearColor(0.163, 0.163, 0.163, 1.0);
@@ -321,15 +272,15 @@ fn main() {
                 // Issue the necessary commands to draw your scene here
                 
                 //==============TASK 1b==============
-                //gl::BindVertexArray(task1_vao);
+                //task1_vao.bind();
                 //gl::DrawElements(gl::TRIANGLES, 18, gl::UNSIGNED_INT, ptr::null());
 
                 //==============TASK 2==============
-                //gl::BindVertexArray(task2_vao);
+                //task2_vao.bind();
                 //gl::DrawElements(gl::TRIANGLES, 9, gl::UNSIGNED_INT, ptr::null());
 
                 //==============TASK 3==============
-                gl::BindVertexArray(task3_vao);
+                task3_vao.bind();
                 gl::DrawElements(gl::TRIANGLES, 3, gl::UNSIGNED_INT, ptr::null());
             }
 
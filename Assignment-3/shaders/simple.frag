@@ -4,6 +4,26 @@ out vec4 color;
   
 in vec4 vertexColor;
 in vec3 vertexNormals;
+in vec3 worldPosition;
+in vec2 vertexTexcoords;
+in vec4 vertexTangent;
+
+layout(location=7) uniform vec3 camera_position;
+
+// Material from the MTL file
+layout(location=10) uniform vec3 material_ambient;
+layout(location=11) uniform vec3 material_diffuse;
+layout(location=12) uniform vec3 material_specular;
+layout(location=13) uniform float material_shininess;
+layout(location=14) uniform float material_alpha;
+
+// Multiplied with the vertex color when the mesh has a diffuse texture
+layout(binding=0) uniform sampler2D diffuse_texture;
+layout(location=15) uniform int use_diffuse_texture;
+
+// Normals in tangent space, replacing the vertex normals when the mesh has a normal map
+layout(binding=1) uniform sampler2D normal_texture;
+layout(location=16) uniform int use_normal_texture;
 
 vec3 lightDirection = normalize(vec3(0.8, -0.5,0.6));
 
@@ -13,5 +33,28 @@ void main()
     //color = vec4(vertexNormals, 1.0f);
 
     //Task 1d
-    color = vec4(vertexColor.xyz*max(0, dot(vertexNormals, -lightDirection)), vertexColor.w);
+    vec4 baseColor = vertexColor;
+    if (use_diffuse_texture != 0) {
+        baseColor *= texture(diffuse_texture, vertexTexcoords);
+    }
+
+    vec3 normal = normalize(vertexNormals);
+    if (use_normal_texture != 0) {
+        vec3 tangent = normalize(vertexTangent.xyz - normal * dot(normal, vertexTangent.xyz));
+        vec3 bitangent = vertexTangent.w * cross(normal, tangent);
+        vec3 mapped = texture(normal_texture, vertexTexcoords).xyz * 2.0 - 1.0;
+        normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+    }
+    vec3 ambient = material_ambient * baseColor.xyz;
+    vec3 diffuse = material_diffuse * baseColor.xyz * max(0, dot(normal, -lightDirection));
+
+    // Blinn-Phong, a shininess of 0 means the material has no highlight
+    vec3 specular = vec3(0.0);
+    if (material_shininess > 0.0) {
+        vec3 viewDirection = normalize(camera_position - worldPosition);
+        vec3 halfway = normalize(viewDirection - lightDirection);
+        specular = material_specular * pow(max(0, dot(normal, halfway)), material_shininess);
+    }
+
+    color = vec4(ambient + diffuse + specular, baseColor.w * material_alpha);
 }
\ No newline at end of file
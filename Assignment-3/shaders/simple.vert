@@ -3,18 +3,29 @@
 in layout(location=0) vec3 position;
 in layout(location=1) vec4 color;
 in layout(location=2) vec3 normals;
+in layout(location=3) vec2 texcoords;
+in layout(location=4) vec4 tangent;
 
 //Task 4
 layout(location=3) uniform mat4 transformation;
 layout(location=4) uniform mat4 normal_transformation;
+layout(location=5) uniform vec4 color_tint;
+layout(location=6) uniform mat4 model_transformation;
 
 out vec4 vertexColor;
 out vec3 vertexNormals;
+out vec3 worldPosition;
+out vec2 vertexTexcoords;
+out vec4 vertexTangent;
 
 void main()
 {
-    vertexColor = color;
+    vertexColor = color * color_tint;
     vertexNormals = normalize(mat3(normal_transformation) * normals);
+    worldPosition = (model_transformation * vec4(position, 1.0)).xyz;
+    vertexTexcoords = texcoords;
+    // Tangents lie in the surface, so they are transformed like positions rather than like normals
+    vertexTangent = vec4(normalize(mat3(model_transformation) * tangent.xyz), tangent.w);
     gl_Position = transformation * vec4(position, 1.0);
 
 }
\ No newline at end of file
@@ -0,0 +1,273 @@
+extern crate nalgebra_glm as glm;
+
+// Axis aligned bounding box
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Aabb {
+    // Bounds of a flat list of x, y, z coordinates, as stored in mesh::Mesh. An empty list gives a box at the origin
+    pub fn from_positions(positions: &[f32]) -> Aabb {
+        if positions.len() < 3 {
+            return Aabb { min: glm::zero(), max: glm::zero() };
+        }
+        let mut aabb = Aabb {
+            min: glm::vec3(f32::MAX, f32::MAX, f32::MAX),
+            max: glm::vec3(f32::MIN, f32::MIN, f32::MIN),
+        };
+        for p in positions.chunks_exact(3) {
+            let point = glm::vec3(p[0], p[1], p[2]);
+            aabb.min = glm::min2(&aabb.min, &point);
+            aabb.max = glm::max2(&aabb.max, &point);
+        }
+        aabb
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Half the size of the box along each axis
+    pub fn extents(&self) -> glm::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn contains(&self, point: &glm::Vec3) -> bool {
+        (0..3).all(|i| self.min[i] <= point[i] && point[i] <= self.max[i])
+    }
+
+    // The smallest axis aligned box around the transformed box
+    pub fn transform(&self, matrix: &glm::Mat4) -> Aabb {
+        let center = (matrix * self.center().push(1.0)).xyz();
+        let extents = self.extents();
+        let mut new_extents = glm::Vec3::zeros();
+        for row in 0..3 {
+            for col in 0..3 {
+                new_extents[row] += matrix[(row, col)].abs() * extents[col];
+            }
+        }
+        Aabb { min: center - new_extents, max: center + new_extents }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Centered on the bounding box of the points, which is not the tightest fit but close enough for culling
+    pub fn from_positions(positions: &[f32]) -> BoundingSphere {
+        let center = Aabb::from_positions(positions).center();
+        let radius = positions.chunks_exact(3)
+            .map(|p| glm::distance(&center, &glm::vec3(p[0], p[1], p[2])))
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    pub fn union(&self, other: &BoundingSphere) -> BoundingSphere {
+        let offset = other.center - self.center;
+        let distance = glm::length(&offset);
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        BoundingSphere {
+            center: self.center + offset * ((radius - self.radius) / distance),
+            radius,
+        }
+    }
+
+    // The radius grows with the largest scale factor of the matrix, so the sphere stays around the transformed points
+    pub fn transform(&self, matrix: &glm::Mat4) -> BoundingSphere {
+        let linear = glm::mat4_to_mat3(matrix);
+        let max_scale = (0..3).map(|i| linear.column(i).norm()).fold(0.0, f32::max);
+        BoundingSphere {
+            center: (matrix * self.center.push(1.0)).xyz(),
+            radius: self.radius * max_scale,
+        }
+    }
+}
+
+// The six planes of a view frustum, with normals pointing inwards
+pub struct Frustum {
+    planes: [glm::Vec4; 6],
+}
+
+impl Frustum {
+    // Extracts the planes from a view projection matrix, so they are in world space
+    pub fn from_matrix(view_projection: &glm::Mat4) -> Frustum {
+        let row = |i: usize| glm::row(view_projection, i);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let normalize = |plane: glm::Vec4| plane / glm::length(&plane.xyz());
+        Frustum {
+            planes: [
+                normalize(w + x), // Left
+                normalize(w - x), // Right
+                normalize(w + y), // Bottom
+                normalize(w - y), // Top
+                normalize(w + z), // Near
+                normalize(w - z), // Far
+            ],
+        }
+    }
+
+    // Conservative, a box that is near a corner of the frustum may be reported as intersecting even though it is outside
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal is the last one to leave the frustum
+            let corner = glm::vec3(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            glm::dot(&plane.xyz(), &corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| glm::dot(&plane.xyz(), &sphere.center) + plane.w >= -sphere.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners(aabb: &Aabb) -> Vec<glm::Vec3> {
+        (0..8).map(|i| glm::vec3(
+            if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        )).collect()
+    }
+
+    // Rounding can put a transformed corner a hair outside the box
+    fn grown(aabb: &Aabb) -> Aabb {
+        let margin = glm::vec3(1e-4, 1e-4, 1e-4);
+        Aabb { min: aabb.min - margin, max: aabb.max + margin }
+    }
+
+    #[test]
+    fn aabb_from_positions() {
+        let aabb = Aabb::from_positions(&[1.0, -2.0, 3.0, -1.0, 5.0, 0.0, 0.5, 0.5, 0.5]);
+        assert_eq!(aabb.min, glm::vec3(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, glm::vec3(1.0, 5.0, 3.0));
+        assert_eq!(Aabb::from_positions(&[]).max, glm::zero::<glm::Vec3>());
+    }
+
+    #[test]
+    fn transformed_aabb_contains_the_transformed_corners() {
+        let aabb = Aabb { min: glm::vec3(-1.0, -2.0, 0.5), max: glm::vec3(3.0, 1.0, 2.0) };
+        let matrix = glm::translation(&glm::vec3(4.0, -1.0, 2.0))
+            * glm::rotation(0.7, &glm::vec3(1.0, 2.0, 0.5))
+            * glm::scaling(&glm::vec3(2.0, 0.5, 3.0));
+        let transformed = grown(&aabb.transform(&matrix));
+        for corner in corners(&aabb) {
+            let corner = (matrix * corner.push(1.0)).xyz();
+            assert!(transformed.contains(&corner), "{} is outside {:?}", corner, transformed);
+        }
+
+        // A quarter turn keeps the box tight
+        let quarter_turn = glm::rotation(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+        let turned = aabb.transform(&quarter_turn);
+        assert!(glm::distance(&turned.min, &glm::vec3(-1.0, -1.0, 0.5)) < 1e-5, "{:?}", turned);
+        assert!(glm::distance(&turned.max, &glm::vec3(2.0, 3.0, 2.0)) < 1e-5, "{:?}", turned);
+    }
+
+    // A camera at eye looking down -z, with a 1 radian field of view and near and far planes at 1 and 100
+    fn frustum(eye: &glm::Vec3) -> Frustum {
+        let view = glm::look_at(eye, &(eye - glm::vec3(0.0, 0.0, 1.0)), &glm::vec3(0.0, 1.0, 0.0));
+        Frustum::from_matrix(&(glm::perspective(1.0, 1.0, 1.0, 100.0) * view))
+    }
+
+    #[test]
+    fn frustum_planes() {
+        let eye = glm::vec3(3.0, 2.0, 5.0);
+        let frustum = frustum(&eye);
+        let small_box = |center: glm::Vec3| Aabb { min: center - glm::vec3(0.1, 0.1, 0.1), max: center + glm::vec3(0.1, 0.1, 0.1) };
+        let small_sphere = |center: glm::Vec3| BoundingSphere { center, radius: 0.1 };
+
+        // Ten units ahead the frustum reaches 10 * tan(0.5), about 5.46, to each side
+        let inside = eye + glm::vec3(0.0, 0.0, -10.0);
+        assert!(frustum.intersects_aabb(&small_box(inside)));
+        assert!(frustum.intersects_sphere(&small_sphere(inside)));
+        // Pairs of points just outside and just inside each plane
+        let planes = [
+            (glm::vec3(-7.0, 0.0, -10.0), glm::vec3(-5.0, 0.0, -10.0)), // Left
+            (glm::vec3(7.0, 0.0, -10.0), glm::vec3(5.0, 0.0, -10.0)),   // Right
+            (glm::vec3(0.0, -7.0, -10.0), glm::vec3(0.0, -5.0, -10.0)), // Bottom
+            (glm::vec3(0.0, 7.0, -10.0), glm::vec3(0.0, 5.0, -10.0)),   // Top
+            (glm::vec3(0.0, 0.0, -0.5), glm::vec3(0.0, 0.0, -1.5)),     // Near
+            (glm::vec3(0.0, 0.0, -150.0), glm::vec3(0.0, 0.0, -95.0)),  // Far
+        ];
+        for (outside, inside) in &planes {
+            let center = eye + outside;
+            assert!(!frustum.intersects_aabb(&small_box(center)), "Box at {} is inside", outside);
+            assert!(!frustum.intersects_sphere(&small_sphere(center)), "Sphere at {} is inside", outside);
+            let center = eye + inside;
+            assert!(frustum.intersects_aabb(&small_box(center)), "Box at {} is outside", inside);
+            assert!(frustum.intersects_sphere(&small_sphere(center)), "Sphere at {} is outside", inside);
+        }
+    }
+
+    #[test]
+    fn frustum_keeps_bounds_that_cross_a_plane() {
+        let eye = glm::vec3(0.0, 0.0, 0.0);
+        let frustum = frustum(&eye);
+        // The centers are outside the left plane, but the bounds reach back in
+        let center = glm::vec3(-6.0, 0.0, -10.0);
+        assert!(frustum.intersects_sphere(&BoundingSphere { center, radius: 1.0 }));
+        assert!(frustum.intersects_aabb(&Aabb { min: center - glm::vec3(1.0, 1.0, 1.0), max: center + glm::vec3(1.0, 1.0, 1.0) }));
+        assert!(!frustum.intersects_sphere(&BoundingSphere { center: glm::vec3(-9.0, 0.0, -10.0), radius: 1.0 }));
+    }
+
+    #[test]
+    fn union_of_nested_spheres_is_the_outer_one() {
+        let outer = BoundingSphere { center: glm::vec3(1.0, 0.0, 0.0), radius: 5.0 };
+        let inner = BoundingSphere { center: glm::vec3(2.0, 1.0, 0.0), radius: 1.0 };
+        for union in &[outer.union(&inner), inner.union(&outer)] {
+            assert_eq!(union.center, outer.center);
+            assert_eq!(union.radius, outer.radius);
+        }
+    }
+
+    #[test]
+    fn union_of_disjoint_spheres_touches_both() {
+        let a = BoundingSphere { center: glm::vec3(0.0, 0.0, 0.0), radius: 1.0 };
+        let b = BoundingSphere { center: glm::vec3(10.0, 0.0, 0.0), radius: 3.0 };
+        let union = a.union(&b);
+        // From the far side of a to the far side of b
+        assert!((union.radius - 7.0).abs() < 1e-5);
+        assert!(glm::distance(&union.center, &glm::vec3(6.0, 0.0, 0.0)) < 1e-5);
+    }
+
+    #[test]
+    fn sphere_radius_follows_the_largest_scale() {
+        let sphere = BoundingSphere { center: glm::vec3(1.0, 0.0, 0.0), radius: 2.0 };
+        let matrix = glm::translation(&glm::vec3(0.0, 5.0, 0.0)) * glm::scaling(&glm::vec3(1.0, 3.0, 0.5));
+        let transformed = sphere.transform(&matrix);
+        assert!((transformed.radius - 6.0).abs() < 1e-5);
+        assert!(glm::distance(&transformed.center, &glm::vec3(1.0, 5.0, 0.0)) < 1e-5);
+
+        // Points on the sphere stay inside it
+        for direction in &[glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, -1.0), glm::normalize(&glm::vec3(1.0, 1.0, 1.0))] {
+            let point = (matrix * (sphere.center + direction * sphere.radius).push(1.0)).xyz();
+            assert!(glm::distance(&point, &transformed.center) <= transformed.radius + 1e-4);
+        }
+    }
+}
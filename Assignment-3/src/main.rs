@@ -1,168 +1,165 @@
 extern crate nalgebra_glm as glm;
 use gl::types::*;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::{mem, os::raw::c_void, ptr, str, ffi::CString};
+use std::{ptr, str, ffi::CString};
 
-mod shader;
 mod util;
+mod bounds;
 mod mesh;
+mod noise;
 mod scene_graph;
+mod scene_file;
+mod terrain;
+mod picking;
+mod texture;
 mod toolbox;
 
 use glutin::event::{
     ElementState::{Pressed, Released},
-    Event, KeyboardInput,
+    Event, KeyboardInput, MouseButton,
     VirtualKeyCode::{self, *},
     WindowEvent,
 };
 use glutin::event_loop::ControlFlow;
 
+use gloom_common::{shader, vertex};
+
 const SCREEN_W: u32 = 600;
 const SCREEN_H: u32 = 600;
 
-// Helper functions to make interacting with OpenGL a little bit prettier. You will need these!
-// The names should be pretty self explanatory
-fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+// Command line options. Everything is optional, so the program still runs with a plain cargo run
+struct Options {
+    // Scene file to load instead of the built-in scene, and to save to with F5
+    scene: Option<String>,
+    // An OBJ model, a heightmap image, or "noise" for generated terrain. The lunar surface if not given
+    terrain: Option<String>,
+    // Seed for the noise terrain
+    seed: u32,
 }
 
-// Get the OpenGL-compatible pointer to an arbitrary array of numbers
-fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
+impl Options {
+    fn from_args() -> Options {
+        let mut options = Options { scene: None, terrain: None, seed: 0 };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scene" => options.scene = args.next(),
+                "--terrain" => options.terrain = args.next(),
+                "--seed" => match args.next().map(|seed| seed.parse()) {
+                    Some(Ok(seed)) => options.seed = seed,
+                    _ => println!("--seed needs a whole number"),
+                },
+                other => println!("Ignoring unknown argument {}", other),
+            }
+        }
+        options
+    }
 }
 
-// Get the size of the given type in bytes
-fn size_of<T>() -> i32 {
-    mem::size_of::<T>() as i32
+// Where F5 saves the scene when no scene file was given
+const DEFAULT_SCENE_FILE: &str = "./resources/scene.txt";
+
+// The render layers F2 cycles through, starting with the first
+const PASS_LAYERS: [(&str, u32); 3] = [
+    ("the default layer", scene_graph::LAYER_DEFAULT),
+    ("all layers", scene_graph::LAYER_ALL),
+    ("the debug layer", scene_graph::LAYER_DEBUG),
+];
+
+//=====TASK 1B=====
+unsafe fn create_vao(mesh: &mesh::Mesh) -> vertex::Vao {
+    vertex::VaoBuilder::new(&mesh.indices)
+        .attribute(vertex::Attribute::Position, &mesh.vertices)
+        .attribute(vertex::Attribute::Color, &mesh.colors)
+        .attribute(vertex::Attribute::Normal, &mesh.normals)
+        .attribute(vertex::Attribute::Texcoord, &mesh.texcoords)
+        .attribute(vertex::Attribute::Tangent, &mesh.tangents)
+        .build()
 }
 
-// Get an offset in bytes for n units of type T
-fn offset<T>(n: u32) -> *const c_void {
-    (n * mem::size_of::<T>() as u32) as *const T as *const c_void
+// Counts the draw calls of a frame, for profiling
+#[derive(Default)]
+struct DrawStats {
+    drawn: u32,
+    culled: u32,
 }
 
-//=====TASK 1B=====
-unsafe fn create_vao(vertices: &Vec<f32>, indices: &Vec<u32>, vertex_colors: &Vec<f32>, vertex_normals: &Vec<f32>) -> u32 {
-    let mut array_id: u32 = 0;
-    gl::GenVertexArrays(1, &mut array_id);
-    gl::BindVertexArray(array_id);
-
-    let mut vertex_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut vertex_buffer_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertices),
-        pointer_to_array(vertices),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-    gl::EnableVertexAttribArray(0);
-
-    let mut index_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut index_buffer_id);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer_id);
-    gl::BufferData(
-        gl::ELEMENT_ARRAY_BUFFER,
-        byte_size_of_array(indices),
-        pointer_to_array(indices),
-        gl::STATIC_DRAW,
-    );
-
-    let mut vertex_color_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut vertex_color_buffer_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_color_buffer_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertex_colors),
-        pointer_to_array(vertex_colors),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-    gl::EnableVertexAttribArray(1);
-
-    let mut vertex_normal_buffer_id: u32 = 0;
-    gl::GenBuffers(1, &mut vertex_normal_buffer_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_normal_buffer_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertex_normals),
-        pointer_to_array(vertex_normals),
-        gl::STATIC_DRAW,
-    );
-    gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
-    gl::EnableVertexAttribArray(2);
-
-    return array_id;
+// Everything draw_scene needs to know about the camera, and which render layers it should draw
+struct RenderPass {
+    view_projection_matrix: glm::Mat4,
+    camera_position: glm::Vec3,
+    frustum: bounds::Frustum,
+    layers: u32,
 }
 
-unsafe fn draw_scene(root: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4) {
-    // Check if node is drawable, set uniforms, draw
-    if (root.index_count > 0) {
-        gl::UniformMatrix4fv(3, 1, 0, (view_projection_matrix * root.current_transformation_matrix).as_ptr());
-        gl::UniformMatrix4fv(4, 1, 0, (root.current_transformation_matrix).as_ptr());
-        gl::BindVertexArray(root.vao_id);
-        gl::DrawElements(gl::TRIANGLES, root.index_count, gl::UNSIGNED_INT, ptr::null());
+impl RenderPass {
+    fn new(view_matrix: glm::Mat4, projection_matrix: glm::Mat4, layers: u32) -> RenderPass {
+        let view_projection_matrix = projection_matrix * view_matrix;
+        RenderPass {
+            view_projection_matrix,
+            camera_position: (glm::inverse(&view_matrix) * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz(),
+            frustum: bounds::Frustum::from_matrix(&view_projection_matrix),
+            layers,
+        }
     }
 
-    // Recurse
-    for &child in &root.children {
-        draw_scene(&*child, view_projection_matrix);
+    // Sets the uniforms that are the same for every node
+    unsafe fn set_uniforms(&self, shader: &shader::Shader) {
+        shader.set_vec3("camera_position", &self.camera_position);
     }
 }
 
-unsafe fn update_node_transformations(root:&mut scene_graph::SceneNode, transformation_so_far: &glm::Mat4) {
-    
-    // Construct the correct transformation matrix
-    let origin = glm::mat4(
-        1.0, 0.0, 0.0, root.reference_point[0],
-        0.0, 1.0, 0.0, root.reference_point[1],
-        0.0, 0.0, 1.0, root.reference_point[2],
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let rotateX = glm::mat4(
-        1.0, 0.0, 0.0, 0.0,
-        0.0, root.rotation[0].cos(), -root.rotation[0].sin(), 0.0,
-        0.0, root.rotation[0].sin(), root.rotation[0].cos(), 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let rotateY = glm::mat4(
-        root.rotation[1].cos(), 0.0, root.rotation[1].sin(), 0.0,
-        0.0, 1.0, 0.0, 0.0,
-        -root.rotation[1].sin(), 0.0, root.rotation[1].cos(), 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let rotateZ = glm::mat4(
-        root.rotation[2].cos(), -root.rotation[2].sin(), 0.0, 0.0,
-        root.rotation[2].sin(), root.rotation[2].cos(), 0.0, 0.0,
-        0.0, 0.0, 1.0, 0.0,
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let notorigin = glm::mat4(
-        1.0, 0.0, 0.0, -root.reference_point[0],
-        0.0, 1.0, 0.0, -root.reference_point[1],
-        0.0, 0.0, 1.0, -root.reference_point[2],
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    let translation = glm::mat4(
-        1.0, 0.0, 0.0, root.position[0],
-        0.0, 1.0, 0.0, root.position[1],
-        0.0, 0.0, 1.0, root.position[2],
-        0.0, 0.0, 0.0, 1.0,
-    );
-
-    // Update the node's transformation matrix
-    root.current_transformation_matrix = transformation_so_far * translation * origin * rotateX * rotateY * rotateZ * notorigin;
+unsafe fn draw_scene(graph: &scene_graph::SceneGraph, node_id: scene_graph::NodeId, shader: &shader::Shader, pass: &RenderPass, stats: &mut DrawStats) {
+    let node = &graph[node_id];
+
+    // Hidden nodes hide their whole subtree
+    if !node.visible() { return }
+
+    // Skip the whole subtree if its bounds are outside the view. Subtrees without bounds, because they are
+    // unbounded or have no meshes, are always drawn
+    let visible = match (&node.world_bounding_sphere, &node.world_aabb) {
+        (Some(sphere), Some(aabb)) => pass.frustum.intersects_sphere(sphere) && pass.frustum.intersects_aabb(aabb),
+        _ => true,
+    };
+    if !visible {
+        stats.culled += node.drawable_count(pass.layers);
+        return;
+    }
+
+    // Check if node is drawable in this pass, set uniforms, draw
+    if (node.index_count() > 0 && node.in_layers(pass.layers)) {
+        stats.drawn += 1;
+        shader.set_mat4("transformation", &(pass.view_projection_matrix * node.current_transformation_matrix));
+        // Normals have to be transformed by the inverse transpose to stay perpendicular to non-uniformly scaled surfaces
+        shader.set_mat4("normal_transformation", &glm::inverse_transpose(node.current_transformation_matrix));
+        shader.set_vec4("color_tint", &node.color);
+        shader.set_mat4("model_transformation", &node.current_transformation_matrix);
+
+        let material = &node.material;
+        shader.set_vec3("material_ambient", &material.ambient);
+        shader.set_vec3("material_diffuse", &material.diffuse);
+        shader.set_vec3("material_specular", &material.specular);
+        shader.set_f32("material_shininess", material.shininess);
+        shader.set_f32("material_alpha", material.alpha);
+
+        if let Some(texture_id) = node.diffuse_texture {
+            texture::bind(texture_id, 0);
+        }
+        shader.set_bool("use_diffuse_texture", node.diffuse_texture.is_some());
+        if let Some(texture_id) = node.normal_texture {
+            texture::bind(texture_id, 1);
+        }
+        shader.set_bool("use_normal_texture", node.normal_texture.is_some());
+
+        gl::BindVertexArray(node.vao_id);
+        gl::DrawElements(gl::TRIANGLES, node.index_count(), gl::UNSIGNED_INT, ptr::null());
+    }
 
     // Recurse
-    for &child in &root.children {
-        update_node_transformations(&mut *child, &root.current_transformation_matrix);
+    for &child in node.children() {
+        draw_scene(graph, child, shader, pass, stats);
     }
 }
 
@@ -175,10 +172,17 @@ fn main() {
         .with_inner_size(glutin::dpi::LogicalSize::new(SCREEN_W, SCREEN_H));
     let cb = glutin::ContextBuilder::new().with_vsync(true);
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
+    let options = Options::from_args();
     // Set up a shared vector for keeping track of currently pressed keys
     let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
     // Send a copy of this vector to send to the render thread
     let pressed_keys = Arc::clone(&arc_pressed_keys);
+    // Set up a shared slot for the cursor position of the last mouse click, which the render thread picks from
+    let arc_mouse_click = Arc::new(Mutex::new(None::<(f32, f32)>));
+    let mouse_click = Arc::clone(&arc_mouse_click);
+    // Keys that went down since the last frame, for toggles that should happen once per press rather than every frame
+    let arc_key_presses = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::new()));
+    let key_presses = Arc::clone(&arc_key_presses);
 
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
@@ -203,64 +207,160 @@ fn main() {
 
         // == // Set up your VAO here
         
-        let terrain = mesh::Terrain::load("./resources/lunarsurface.obj");
-        let terrainVAO = unsafe { create_vao(&terrain.vertices, &terrain.indices, &terrain.colors, &terrain.normals) };
-
-        let helicopter = mesh::Helicopter::load("./resources/helicopter.obj");
-        let hBodyVao = unsafe { create_vao(&helicopter.body.vertices, &helicopter.body.indices, &helicopter.body.colors, &helicopter.body.normals) };
-        let hMainVao = unsafe { create_vao(&helicopter.main_rotor.vertices, &helicopter.main_rotor.indices, &helicopter.main_rotor.colors, &helicopter.main_rotor.normals) };
-        let hTailVao = unsafe { create_vao(&helicopter.tail_rotor.vertices, &helicopter.tail_rotor.indices, &helicopter.tail_rotor.colors, &helicopter.tail_rotor.normals) };
-        let hDoorVao = unsafe { create_vao(&helicopter.door.vertices, &helicopter.door.indices, &helicopter.door.colors, &helicopter.door.normals) };
-
-        //Scene graph
-        //Define nodes
-
-        const numHelicopters: usize = 5;
-
-        let mut globalRootNode = scene_graph::SceneNode::new();
+        //Generated terrain is about as large as the lunar surface
+        let terrainScale = mesh::TerrainScale { horizontal: 2.0, vertical: 40.0 };
+        let terrain = match options.terrain.as_deref() {
+            None => mesh::Terrain::load("./resources/lunarsurface.obj"),
+            Some("noise") => {
+                let noise = mesh::TerrainNoise { seed: options.seed, frequency: 0.02, octaves: 5, persistence: 0.5 };
+                Ok(mesh::Terrain::from_noise(257, 257, &noise, &terrainScale))
+            }
+            Some(path) if path.ends_with(".obj") => mesh::Terrain::load(path),
+            Some(path) => mesh::Terrain::from_heightmap(path, &terrainScale),
+        }.unwrap_or_else(|e| panic!("{}", e));
+        let terrainQuery = terrain::TerrainQuery::new(&terrain);
+        let terrainVAO = unsafe { create_vao(&terrain) };
+
+        let helicopter = mesh::Model::load_expecting(
+            "./resources/helicopter.obj",
+            &[
+                ("Body_body", "body"),
+                ("Main_Rotor_main_rotor", "main_rotor"),
+                ("Tail_Rotor_tail_rotor", "tail_rotor"),
+                ("Door_door", "door"),
+            ],
+        ).unwrap_or_else(|e| panic!("{}", e));
+        let helicopterVAOs: Vec<vertex::Vao> = helicopter.parts.iter()
+            .map(|part| unsafe { create_vao(&part.mesh) })
+            .collect();
+
+        //Primitives for debugging scenes, "primitives/cube" and so on in scene files
+        let primitives = [
+            ("plane", mesh::primitives::plane(1.0, 1.0, 1, 1)),
+            ("cube", mesh::primitives::cube(1.0, 1)),
+            ("uv_sphere", mesh::primitives::uv_sphere(0.5, 32, 16)),
+            ("icosphere", mesh::primitives::icosphere(0.5, 3)),
+            ("cylinder", mesh::primitives::cylinder(0.5, 1.0, 32)),
+            ("cone", mesh::primitives::cone(0.5, 1.0, 32)),
+            ("torus", mesh::primitives::torus(0.5, 0.15, 32, 16)),
+            ("disk", mesh::primitives::disk(0.5, 32)),
+        ];
+        let primitiveVAOs: Vec<vertex::Vao> = primitives.iter()
+            .map(|(_, mesh)| unsafe { create_vao(mesh) })
+            .collect();
+
+        //Meshes that nodes, and scene files, refer to by name
+        let mut meshes: HashMap<String, (u32, &mesh::Mesh)> = HashMap::new();
+        meshes.insert("terrain".to_string(), (terrainVAO.vao_id(), &terrain));
+        for (part, vao) in helicopter.parts.iter().zip(&helicopterVAOs) {
+            meshes.insert(helicopter.mesh_name(part), (vao.vao_id(), &part.mesh));
+        }
+        for ((name, mesh), vao) in primitives.iter().zip(&primitiveVAOs) {
+            meshes.insert(format!("primitives/{}", name), (vao.vao_id(), mesh));
+        }
 
-        let mut terrainNode = scene_graph::SceneNode::from_vao(terrainVAO, terrain.index_count);
+        //Textures of the materials, by path, so meshes that share a texture share the upload
+        let mut textures: HashMap<String, texture::Texture> = HashMap::new();
+        for (_, mesh) in meshes.values() {
+            let material = &mesh.material;
+            for path in material.diffuse_texture.iter().chain(material.normal_texture.iter()) {
+                if textures.contains_key(path) { continue }
+                match unsafe { texture::Texture::load(path, &texture::TextureOptions::default()) } {
+                    Ok(texture) => { textures.insert(path.clone(), texture); }
+                    Err(e) => println!("Failed to load texture {}: {}", path, e),
+                }
+            }
+        }
 
-        //Connect nodes
-        globalRootNode.add_child(&terrainNode);
+        let meshNode = |name: &str| meshes.get(name).map(|&(vao, mesh)| {
+            let mut node = scene_graph::SceneNode::from_mesh(vao, mesh).with_mesh_name(name);
+            node.diffuse_texture = mesh.material.diffuse_texture.as_ref()
+                .and_then(|path| textures.get(path))
+                .map(|texture| texture.texture_id);
+            node.normal_texture = mesh.material.normal_texture.as_ref()
+                .filter(|_| !mesh.tangents.is_empty())
+                .and_then(|path| textures.get(path))
+                .map(|texture| texture.texture_id);
+            node
+        });
 
-        let mut helicopterNodes = Vec::with_capacity(5 * numHelicopters);
+        //Scene graph
+        let mut sceneGraph = scene_graph::SceneGraph::new();
+        let globalRootNode = sceneGraph.root();
+
+        //Load the scene file if one was given. If there is none, or it doesn't load, build the default scene
+        let loaded = match &options.scene {
+            Some(path) => match scene_file::load(path, &mut sceneGraph, globalRootNode, &meshNode) {
+                Ok(_) => true,
+                Err(e) => {
+                    println!("{}", e);
+                    println!("Using the built-in scene instead");
+                    false
+                }
+            },
+            None => false,
+        };
+        if !loaded {
+            //Define nodes
 
-        //helicopters
-        for i in 0..numHelicopters {
-            let mut helicopterRootNode = scene_graph::SceneNode::new();
-            let mut hBodyNode = scene_graph::SceneNode::from_vao(hBodyVao, helicopter.body.index_count);
-            let mut hMainNode = scene_graph::SceneNode::from_vao(hMainVao, helicopter.main_rotor.index_count);
-            let mut hTailNode = scene_graph::SceneNode::from_vao(hTailVao, helicopter.tail_rotor.index_count);
-            let mut hDoorNode = scene_graph::SceneNode::from_vao(hDoorVao, helicopter.door.index_count);
-            
-            terrainNode.add_child(&helicopterRootNode);
+            const numHelicopters: usize = 5;
 
-            helicopterRootNode.add_child(&hBodyNode);
-            helicopterRootNode.add_child(&hMainNode);
-            helicopterRootNode.add_child(&hTailNode);
-            helicopterRootNode.add_child(&hDoorNode);
+            let terrainNode = sceneGraph.add_node(meshNode("terrain").unwrap().with_name("terrain"));
 
-            //Tail rotor origin
-            hTailNode.reference_point = glm::vec3(0.35, 2.3, 10.4);
+            //Connect nodes
+            sceneGraph.add_child(globalRootNode, terrainNode);
 
-            helicopterNodes.push(helicopterRootNode);
-            helicopterNodes.push(hBodyNode);
-            helicopterNodes.push(hMainNode);
-            helicopterNodes.push(hTailNode);
-            helicopterNodes.push(hDoorNode);
+            //helicopters
+            for i in 0..numHelicopters {
+                let helicopterRootNode = helicopter.instantiate(&mut sceneGraph, terrainNode, meshNode).unwrap();
+                sceneGraph[helicopterRootNode].name = Some(format!("helicopter_{}", i));
+                sceneGraph[helicopterRootNode].tags.push("helicopter".to_string());
+
+                let part = |name: &str| sceneGraph.find_from(helicopterRootNode, name).unwrap();
+                let (hBodyNode, hMainNode, hTailNode, hDoorNode) =
+                    (part("body"), part("main_rotor"), part("tail_rotor"), part("door"));
+
+                sceneGraph[hBodyNode].color = glm::vec4(0.3, 0.3, 0.3, 1.0);
+                sceneGraph[hMainNode].color = glm::vec4(0.3, 0.1, 0.1, 1.0);
+                sceneGraph[hTailNode].color = glm::vec4(0.1, 0.3, 0.1, 1.0);
+                sceneGraph[hDoorNode].color = glm::vec4(0.1, 0.1, 0.3, 1.0);
+                sceneGraph[hMainNode].tags.push("rotor".to_string());
+                sceneGraph[hTailNode].tags.push("rotor".to_string());
+
+                //Tail rotor origin
+                sceneGraph[hTailNode].set_reference_point(glm::vec3(0.35, 2.3, 10.4));
+            }
         }
+        let sceneFile = options.scene.clone().unwrap_or_else(|| DEFAULT_SCENE_FILE.to_string());
+
+        //Helicopters are below the terrain node, so they fly this high above the ground in the terrain's space
+        const helicopterAltitude: f32 = 15.0;
+
+        //Look up the animated parts of each helicopter by name. Scene files can be edited by hand, so
+        //helicopters that are missing a part are left alone rather than animated
+        let helicopterNodes: Vec<_> = sceneGraph.tagged("helicopter").filter_map(|hRootNode| {
+            let part = |name: &str| sceneGraph.find_from(hRootNode, name);
+            match (part("main_rotor"), part("tail_rotor")) {
+                (Some(hMainNode), Some(hTailNode)) => Some((hRootNode, hMainNode, hTailNode)),
+                _ => {
+                    println!("Warning: Not animating helicopter {}, it needs a main_rotor and a tail_rotor",
+                        sceneGraph[hRootNode].name.as_deref().unwrap_or("<unnamed>"));
+                    None
+                }
+            }
+        }).collect();
 
         
-        let shader = unsafe {
+        let mut shader = unsafe {
             shader::ShaderBuilder::new()
                 .attach_file("./shaders/simple.vert")
-                .attach_file("./shaders/simple.frag")
-                .link()
+                .and_then(|builder| builder.attach_file("./shaders/simple.frag"))
+                .and_then(|builder| builder.link())
+                .unwrap_or_else(|e| panic!("{}", e))
         };
 
         unsafe {
-            gl::UseProgram(shader.program_id);
+            shader.activate();
         }
 
 
@@ -271,6 +371,11 @@ fn main() {
 
         let first_frame_time = std::time::Instant::now();
         let mut last_frame_time = first_frame_time;
+        let mut last_stats_time = first_frame_time;
+        // Toggled with F3
+        let mut show_stats = false;
+        // Index into PASS_LAYERS, changed with F2
+        let mut pass_layers = 0;
         // The main rendering loop
         loop {
             let now = std::time::Instant::now();
@@ -278,20 +383,45 @@ fn main() {
             let delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
 
+            // Pick up changes to the shader files, keeping the old program if the new one doesn't build
+            match unsafe { shader.reload_if_changed() } {
+                Ok(true) => unsafe { shader.activate() },
+                Ok(false) => {}
+                Err(e) => println!("{}", e),
+            }
+
             //============================ Helicopter animation ============================
-            for i in 0..numHelicopters {
-                helicopterNodes[i*5+2].rotation[1] = elapsed*20.0;
-                helicopterNodes[i*5+3].rotation[0] = elapsed*20.0;
+            for (i, &(hRootNode, hMainNode, hTailNode)) in helicopterNodes.iter().enumerate() {
+                sceneGraph[hMainNode].set_rotation(glm::vec3(0.0, elapsed*20.0, 0.0));
+                sceneGraph[hTailNode].set_rotation(glm::vec3(elapsed*20.0, 0.0, 0.0));
                 
                 let animation = toolbox::simple_heading_animation(elapsed + 0.75*(i as f32));
-                helicopterNodes[i*5].position[0] = animation.x;
-                helicopterNodes[i*5].position[2] = animation.z;
-                helicopterNodes[i*5].rotation[0] = animation.pitch;
-                helicopterNodes[i*5].rotation[1] = animation.yaw;
-                helicopterNodes[i*5].rotation[2] = animation.roll;
+                let ground = terrainQuery.height_at(animation.x, animation.z).unwrap_or(0.0);
+                sceneGraph[hRootNode].set_position(glm::vec3(animation.x, ground + helicopterAltitude, animation.z));
+                sceneGraph[hRootNode].set_orientation(Some(animation.orientation()));
             }
 
             // Handle keyboard input
+            if let Ok(mut presses) = key_presses.lock() {
+                for key in presses.drain(..) {
+                    match key {
+                        VirtualKeyCode::F2 => {
+                            pass_layers = (pass_layers + 1) % PASS_LAYERS.len();
+                            println!("Drawing {}", PASS_LAYERS[pass_layers].0);
+                        }
+                        VirtualKeyCode::F3 => {
+                            show_stats = !show_stats;
+                        }
+                        VirtualKeyCode::F5 => {
+                            match scene_file::save(&sceneFile, &sceneGraph, globalRootNode) {
+                                Ok(()) => println!("Saved the scene to {}", sceneFile),
+                                Err(e) => println!("{}", e),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
             if let Ok(keys) = pressed_keys.lock() {
                 for key in keys.iter() {
                     match key {
@@ -356,15 +486,39 @@ fn main() {
                 );
                 let perspective_transform: glm::Mat4 = glm::perspective(1.0, 1.0, 1.0, 2000.0);
 
-                let viewProjectionMatrix: glm::Mat4 = perspective_transform * rotatex * rotatey * translate;
+                let viewMatrix: glm::Mat4 = rotatex * rotatey * translate;
+                let viewProjectionMatrix: glm::Mat4 = perspective_transform * viewMatrix;
 
                 gl::ClearColor(0.163, 0.163, 0.163, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT);
                 gl::Clear(gl::DEPTH_BUFFER_BIT);
 
                 // Issue the necessary commands to draw your scene here
-                update_node_transformations(&mut globalRootNode, &glm::identity());
-                draw_scene(&globalRootNode, &viewProjectionMatrix);
+                sceneGraph.update_transformations();
+                let pass = RenderPass::new(viewMatrix, perspective_transform, PASS_LAYERS[pass_layers].1);
+                pass.set_uniforms(&shader);
+                let mut stats = DrawStats::default();
+                draw_scene(&sceneGraph, globalRootNode, &shader, &pass, &mut stats);
+
+                if show_stats && now.duration_since(last_stats_time).as_secs_f32() >= 1.0 {
+                    println!("Draw calls: {} drawn, {} culled", stats.drawn, stats.culled);
+                    last_stats_time = now;
+                }
+
+                // Find what was clicked on, if anything
+                if let Some((cursor_x, cursor_y)) = mouse_click.lock().ok().and_then(|mut click| click.take()) {
+                    let size = context.window().inner_size();
+                    let ray = picking::Ray::from_cursor(cursor_x, cursor_y, size.width as f32, size.height as f32, &viewProjectionMatrix);
+                    let meshOf = |node: &scene_graph::SceneNode| node.mesh_name.as_deref()
+                        .and_then(|name| meshes.get(name))
+                        .map(|&(_, mesh)| mesh);
+                    match picking::pick(&sceneGraph, globalRootNode, &ray, pass.layers, meshOf) {
+                        Some(hit) => println!("Picked {} triangle {} at [{:.2}, {:.2}, {:.2}]",
+                            sceneGraph[hit.node].name.as_deref().unwrap_or("<unnamed>"), hit.triangle,
+                            hit.point.x, hit.point.y, hit.point.z),
+                        None => println!("Picked nothing"),
+                    }
+                }
                 
             }
 
@@ -384,6 +538,9 @@ fn main() {
         }
     });
 
+    // The cursor position is only reported when it moves, so remember it for when a button is clicked
+    let mut cursor_position = (0.0f32, 0.0f32);
+
     // Start the event loop -- This is where window events get handled
     el.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
@@ -425,8 +582,12 @@ fn main() {
                             }
                         }
                         Pressed => {
+                            // Held keys repeat their press events, so only the first one counts as a new press
                             if !keys.contains(&keycode) {
                                 keys.push(keycode);
+                                if let Ok(mut presses) = arc_key_presses.lock() {
+                                    presses.push(keycode);
+                                }
                             }
                         }
                     }
@@ -440,6 +601,21 @@ fn main() {
                     _ => {}
                 }
             }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                cursor_position = (position.x as f32, position.y as f32);
+            }
+            // Send left clicks to the render thread for picking
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state: Pressed, button: MouseButton::Left, .. },
+                ..
+            } => {
+                if let Ok(mut click) = arc_mouse_click.lock() {
+                    *click = Some(cursor_position);
+                }
+            }
             _ => {}
         }
     });
@@ -1,92 +1,909 @@
+extern crate nalgebra_glm as glm;
+
 use tobj;
 
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+pub mod primitives;
+
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
 }
 
+// Surface properties from an MTL file. The defaults light a mesh the same way as before materials were supported
+#[derive(Clone, Debug)]
+pub struct Material {
+    #[allow(dead_code)]
+    pub name: String,
+    pub ambient: glm::Vec3,
+    pub diffuse: glm::Vec3,
+    pub specular: glm::Vec3,
+    pub shininess: f32,
+    pub alpha: f32,
+    // Paths to the texture images, relative to the working directory
+    pub diffuse_texture: Option<String>,
+    // Tangent space normals, read from map_Bump
+    pub normal_texture: Option<String>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            name: String::new(),
+            ambient: glm::zero(),
+            diffuse: glm::vec3(1.0, 1.0, 1.0),
+            specular: glm::zero(),
+            shininess: 0.0,
+            alpha: 1.0,
+            diffuse_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+impl From<&tobj::Material> for Material {
+    fn from(material: &tobj::Material) -> Material {
+        Material {
+            name: material.name.clone(),
+            ambient: glm::make_vec3(&material.ambient),
+            diffuse: glm::make_vec3(&material.diffuse),
+            specular: glm::make_vec3(&material.specular),
+            shininess: material.shininess,
+            alpha: material.dissolve,
+            diffuse_texture: Some(material.diffuse_texture.clone()).filter(|path| !path.is_empty()),
+            normal_texture: Some(material.normal_texture.clone()).filter(|path| !path.is_empty()),
+        }
+    }
+}
+
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
+    // Two per vertex, empty if the model has no texture coordinates
+    pub texcoords: Vec<f32>,
+    // Four per vertex, the tangent and the sign of the bitangent, so the bitangent is w * cross(normal, tangent).
+    // Empty if the model has no texture coordinates
+    pub tangents: Vec<f32>,
     pub colors: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
+    pub material: Material,
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+}
+
+// How generate_normals shades a mesh
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum NormalMode {
+    // Every triangle gets its own normal, so the faces are visible
+    Flat,
+    // Normals are averaged across edges where the triangles meet at less than the crease angle, in radians.
+    // Sharper edges stay hard
+    Smooth { crease_angle: f32 },
 }
 
+// Used for models that have no normals. Hard enough to keep the edges of boxes, and soft enough to smooth out terrain
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
 impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
-        Mesh {
+        let aabb = Aabb::from_positions(&mesh.positions);
+        let bounding_sphere = BoundingSphere::from_positions(&mesh.positions);
+        let mut result = Mesh {
             vertices: mesh.positions,
             normals: mesh.normals,
+            texcoords: mesh.texcoords,
+            tangents: vec![],
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
             index_count,
+            material: Material::default(),
+            aabb,
+            bounding_sphere,
+        };
+        // Without normals the lighting would read garbage
+        if result.normals.is_empty() {
+            result.generate_normals(NormalMode::Smooth { crease_angle: DEFAULT_CREASE_ANGLE });
+        } else {
+            result.generate_tangents();
         }
+        result
     }
-}
 
-pub struct Terrain;
-impl Terrain {
-    pub fn load(path: &str) -> Mesh {
-        println!("Loading terrain model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load terrain model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
-        if models.len() > 1 || models.len() == 0 { panic!("Please use a model with a single mesh") }
+    // For meshes made in code. The texture coordinates may be empty, and tangents are generated if they are not
+    pub fn new(vertices: Vec<f32>, normals: Vec<f32>, texcoords: Vec<f32>, indices: Vec<u32>, color: [f32; 4]) -> Self {
+        let num_verts = vertices.len() / 3;
+        let mut mesh = Mesh {
+            aabb: Aabb::from_positions(&vertices),
+            bounding_sphere: BoundingSphere::from_positions(&vertices),
+            colors: generate_color_vec(color, num_verts),
+            index_count: indices.len() as i32,
+            vertices,
+            normals,
+            texcoords,
+            tangents: vec![],
+            indices,
+            material: Material::default(),
+        };
+        mesh.generate_tangents();
+        mesh
+    }
+
+    fn position(&self, vertex: u32) -> glm::Vec3 {
+        let i = vertex as usize * 3;
+        glm::vec3(self.vertices[i], self.vertices[i + 1], self.vertices[i + 2])
+    }
+
+    // Replaces the normals of the mesh. Triangles contribute to the normals of their corners weighted by their area,
+    // so small slivers don't skew them. Vertices are matched by position rather than index, since OBJ files
+    // duplicate vertices at texture seams, and vertices that end up with different normals on different triangles
+    // are split, which can add vertices to the mesh
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        let triangle_count = self.indices.len() / 3;
+
+        // The cross product is twice the area of the triangle, which gives the area weighting for free
+        let weighted_normals: Vec<glm::Vec3> = self.indices.chunks_exact(3).map(|triangle| {
+            let (a, b, c) = (self.position(triangle[0]), self.position(triangle[1]), self.position(triangle[2]));
+            glm::cross(&(b - a), &(c - a))
+        }).collect();
+        let unit_normals: Vec<glm::Vec3> = weighted_normals.iter()
+            .map(|normal| if normal.norm() > 0.0 { glm::normalize(normal) } else { glm::zero() })
+            .collect();
+
+        // Triangles around every position
+        let position_key = |mesh: &Mesh, vertex: u32| {
+            let p = mesh.position(vertex);
+            (p.x.to_bits(), p.y.to_bits(), p.z.to_bits())
+        };
+        let mut triangles_at = std::collections::HashMap::new();
+        for (triangle, corners) in self.indices.chunks_exact(3).enumerate() {
+            for &vertex in corners {
+                triangles_at.entry(position_key(self, vertex)).or_insert_with(Vec::new).push(triangle);
+            }
+        }
 
-        let terrain = models[0].to_owned();
-        println!("Loaded {} with {} points and {} triangles.", terrain.name, terrain.mesh.positions.len() /3, terrain.mesh.indices.len() / 3);
+        let min_cosine = match mode {
+            NormalMode::Flat => None,
+            NormalMode::Smooth { crease_angle } => Some(crease_angle.cos()),
+        };
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+        // Corners that share a vertex and end up with the same normal share the new vertex too
+        let old_vertex_count = self.vertices.len() / 3;
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut normals = Vec::with_capacity(self.vertices.len());
+        let mut texcoords = Vec::with_capacity(self.texcoords.len());
+        let mut colors = Vec::with_capacity(self.colors.len());
+        let mut new_vertices = std::collections::HashMap::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+
+        for triangle in 0..triangle_count {
+            for corner in 0..3 {
+                let vertex = self.indices[triangle * 3 + corner];
+                let normal = match min_cosine {
+                    None => unit_normals[triangle],
+                    Some(min_cosine) => {
+                        let sum = triangles_at[&position_key(self, vertex)].iter()
+                            .filter(|&&other| other == triangle
+                                || glm::dot(&unit_normals[triangle], &unit_normals[other]) >= min_cosine)
+                            .fold(glm::Vec3::zeros(), |sum, &other| sum + weighted_normals[other]);
+                        if sum.norm() > 0.0 { glm::normalize(&sum) } else { unit_normals[triangle] }
+                    }
+                };
+
+                // Normals that only differ by rounding, like those of two triangles in the same plane, count as the same
+                let rounded = |x: f32| (x * 1e5).round() as i32;
+                let key = (vertex, rounded(normal.x), rounded(normal.y), rounded(normal.z));
+                let index = *new_vertices.entry(key).or_insert_with(|| {
+                    let v = vertex as usize;
+                    vertices.extend_from_slice(&self.vertices[v * 3..v * 3 + 3]);
+                    normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+                    if self.texcoords.len() >= old_vertex_count * 2 {
+                        texcoords.extend_from_slice(&self.texcoords[v * 2..v * 2 + 2]);
+                    }
+                    if self.colors.len() >= old_vertex_count * 4 {
+                        colors.extend_from_slice(&self.colors[v * 4..v * 4 + 4]);
+                    }
+                    (vertices.len() / 3 - 1) as u32
+                });
+                indices.push(index);
+            }
+        }
+
+        self.vertices = vertices;
+        self.normals = normals;
+        self.texcoords = texcoords;
+        self.colors = colors;
+        self.indices = indices;
+        self.index_count = self.indices.len() as i32;
+
+        // The old tangents were made for the old normals
+        self.generate_tangents();
+    }
+
+    fn texcoord(&self, vertex: u32) -> glm::Vec2 {
+        let i = vertex as usize * 2;
+        glm::vec2(self.texcoords[i], self.texcoords[i + 1])
+    }
+
+    fn normal(&self, vertex: u32) -> glm::Vec3 {
+        let i = vertex as usize * 3;
+        glm::vec3(self.normals[i], self.normals[i + 1], self.normals[i + 2])
+    }
+
+    // Replaces the tangents of the mesh, which normal maps need, from the normals and texture coordinates.
+    // Works like MikkTSpace with its default settings, which is what most normal maps are baked with, so they line
+    // up without seams. Every triangle contributes its tangent to its corners, made perpendicular to the corner's
+    // normal and weighted by the angle of the corner in the plane perpendicular to the normal. Only the corners
+    // around a vertex that are joined through shared edges, and that agree on whether the texture is mirrored, are
+    // averaged. Every other group of corners, like those on the far side of a mirrored seam, gets a copy of the
+    // vertex, which can add vertices to the mesh. Clears the tangents if the mesh has no texture coordinates
+    pub fn generate_tangents(&mut self) {
+        let vertex_count = self.vertices.len() / 3;
+        if self.texcoords.len() < vertex_count * 2 || self.normals.len() < vertex_count * 3 {
+            self.tangents = vec![];
+            return;
+        }
+
+        // The tangent of every triangle, and whether the texture is mirrored on it.
+        // None where the triangle has no texture area, and so no texture directions
+        let triangles: Vec<Option<(glm::Vec3, bool)>> = self.indices.chunks_exact(3).map(|triangle| {
+            let positions = [self.position(triangle[0]), self.position(triangle[1]), self.position(triangle[2])];
+            let texcoords = [self.texcoord(triangle[0]), self.texcoord(triangle[1]), self.texcoord(triangle[2])];
+            let (edge1, edge2) = (positions[1] - positions[0], positions[2] - positions[0]);
+            let (uv1, uv2) = (texcoords[1] - texcoords[0], texcoords[2] - texcoords[0]);
+            let determinant = uv1.x * uv2.y - uv2.x * uv1.y;
+            if determinant.abs() < 1e-12 {
+                return None;
+            }
+            let tangent = (edge1 * uv2.y - edge2 * uv1.y) / determinant;
+            if tangent.norm() == 0.0 {
+                return None;
+            }
+            Some((glm::normalize(&tangent), determinant < 0.0))
+        }).collect();
+
+        // Like MikkTSpace, vertices with the same position, normal and texture coordinate count as one vertex
+        // when finding shared edges, since OBJ files can have duplicates
+        let mut welded_ids = std::collections::HashMap::new();
+        let welded: Vec<usize> = (0..vertex_count as u32).map(|vertex| {
+            let (p, n, t) = (self.position(vertex), self.normal(vertex), self.texcoord(vertex));
+            let key: Vec<u32> = [p.x, p.y, p.z, n.x, n.y, n.z, t.x, t.y].iter().map(|x| x.to_bits()).collect();
+            let next_id = welded_ids.len();
+            *welded_ids.entry(key).or_insert(next_id)
+        }).collect();
+        let weld = |corner: usize| welded[self.indices[corner] as usize];
+        let next = |corner: usize| corner - corner % 3 + (corner + 1) % 3;
+        let previous = |corner: usize| corner - corner % 3 + (corner + 2) % 3;
+
+        let mut corners_at = vec![vec![]; welded_ids.len()];
+        for corner in 0..self.indices.len() {
+            corners_at[weld(corner)].push(corner);
+        }
+
+        // Joins the corners into groups, each named by one of its corners. Two corners of a vertex are joined
+        // when their triangles agree on the mirroring and share an edge that ends in the vertex
+        let mut groups: Vec<usize> = (0..self.indices.len()).collect();
+        fn group_of(groups: &mut [usize], mut corner: usize) -> usize {
+            while groups[corner] != corner {
+                groups[corner] = groups[groups[corner]];
+                corner = groups[corner];
+            }
+            corner
+        }
+        for corners in &corners_at {
+            for (i, &a) in corners.iter().enumerate() {
+                for &b in &corners[i + 1..] {
+                    let same_mirroring = match (triangles[a / 3], triangles[b / 3]) {
+                        (Some((_, a_mirrored)), Some((_, b_mirrored))) => a_mirrored == b_mirrored,
+                        _ => false,
+                    };
+                    let shared_edge = weld(next(a)) == weld(previous(b)) || weld(previous(a)) == weld(next(b));
+                    if same_mirroring && shared_edge {
+                        let (a, b) = (group_of(&mut groups, a), group_of(&mut groups, b));
+                        groups[a] = b;
+                    }
+                }
+            }
+        }
+
+        // The weighted sum of the tangents and the mirroring of every group
+        let mut sums: std::collections::HashMap<usize, (glm::Vec3, bool)> = std::collections::HashMap::new();
+        for corner in 0..self.indices.len() {
+            let (tangent, mirrored) = match triangles[corner / 3] {
+                Some(triangle) => triangle,
+                None => continue,
+            };
+            let vertex = self.indices[corner];
+            let normal = self.normal(vertex);
+            let perpendicular = |v: glm::Vec3| {
+                let v = v - normal * glm::dot(&normal, &v);
+                if v.norm() > 0.0 { glm::normalize(&v) } else { v }
+            };
+            let position = self.position(vertex);
+            let to_next = perpendicular(self.position(self.indices[next(corner)]) - position);
+            let to_previous = perpendicular(self.position(self.indices[previous(corner)]) - position);
+            let angle = glm::dot(&to_next, &to_previous).clamp(-1.0, 1.0).acos();
+            let group = group_of(&mut groups, corner);
+            let sum = sums.entry(group).or_insert((glm::Vec3::zeros(), mirrored));
+            sum.0 += perpendicular(tangent) * angle;
+        }
+
+        // The first group of a vertex keeps it, and the other groups get copies. Corners of triangles without
+        // texture directions have no group of their own, and use whatever the vertex ends up with.
+        // For every vertex, the group its tangent comes from
+        let mut sources: Vec<Option<usize>> = vec![None; vertex_count];
+        let mut copies = std::collections::HashMap::new();
+        let mut indices = self.indices.clone();
+        for corner in 0..self.indices.len() {
+            if triangles[corner / 3].is_none() {
+                continue;
+            }
+            let vertex = self.indices[corner];
+            let group = group_of(&mut groups, corner);
+            match sources[vertex as usize] {
+                None => sources[vertex as usize] = Some(group),
+                Some(source) if source == group => {}
+                Some(_) => {
+                    indices[corner] = *copies.entry((vertex, group)).or_insert_with(|| {
+                        sources.push(Some(group));
+                        self.copy_vertex(vertex)
+                    });
+                }
+            }
+        }
+        self.indices = indices;
+
+        self.tangents = Vec::with_capacity(sources.len() * 4);
+        for (vertex, source) in sources.iter().enumerate() {
+            let normal = self.normal(vertex as u32);
+            let (sum, mirrored) = source.and_then(|group| sums.get(&group).cloned()).unwrap_or((glm::Vec3::zeros(), false));
+            // The corners were made perpendicular to the same normal, but their sum can still be zero.
+            // Vertices without a usable tangent get any vector perpendicular to the normal
+            let mut tangent = sum - normal * glm::dot(&normal, &sum);
+            if tangent.norm() < 1e-6 {
+                let axis = if normal.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+                tangent = glm::cross(&normal, &axis);
+            }
+            let tangent = if tangent.norm() > 0.0 { glm::normalize(&tangent) } else { glm::vec3(1.0, 0.0, 0.0) };
+            let w = if mirrored { -1.0 } else { 1.0 };
+            self.tangents.extend_from_slice(&[tangent.x, tangent.y, tangent.z, w]);
+        }
+    }
+
+    // Appends a copy of a vertex, without its tangent, and returns the index of the copy
+    fn copy_vertex(&mut self, vertex: u32) -> u32 {
+        let v = vertex as usize;
+        let copy = self.vertices.len() / 3;
+        self.vertices.extend_from_within(v * 3..v * 3 + 3);
+        self.normals.extend_from_within(v * 3..v * 3 + 3);
+        self.texcoords.extend_from_within(v * 2..v * 2 + 2);
+        if self.colors.len() >= (v + 1) * 4 {
+            self.colors.extend_from_within(v * 4..v * 4 + 4);
+        }
+        copy as u32
     }
 }
 
-use std::ops::Index;
-pub struct Helicopter {
-    pub body: Mesh,
-    pub main_rotor: Mesh,
-    pub tail_rotor: Mesh,
-    pub door: Mesh,
+// Errors from loading models, instead of panicking, so callers can report them or fall back to something else
+#[derive(Debug)]
+pub enum ModelError {
+    Load { path: String, error: tobj::LoadError },
+    MissingObject { path: String, name: String },
+    UnexpectedObjects { path: String, names: Vec<String> },
+    Image { path: String, error: image::ImageError },
+    // Terrain needs at least two rows and two columns of heights
+    HeightmapTooSmall { path: String, width: u32, height: u32 },
 }
 
-// You can use square brackets to access the components of the helicopter, if you want to use loops!
-impl Index<usize> for Helicopter {
-    type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
-        match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
-            _ => panic!("Invalid index, try [0,3]"),
+impl std::fmt::Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ModelError::Load { path, error } => write!(f, "Failed to load model {}: {}", path, error),
+            ModelError::MissingObject { path, name } => write!(f, "Model {} has no object named {}", path, name),
+            ModelError::UnexpectedObjects { path, names } => write!(f, "Model {} has unexpected objects: {}", path, names.join(", ")),
+            ModelError::Image { path, error } => write!(f, "Failed to load image {}: {}", path, error),
+            ModelError::HeightmapTooSmall { path, width, height } =>
+                write!(f, "Heightmap {} is {}x{}, but needs to be at least 2x2", path, width, height),
         }
     }
 }
 
-impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
+impl std::error::Error for ModelError {}
+
+// One object from an OBJ file
+pub struct Part {
+    // The object's name in the file, unless the model was loaded with load_expecting, which renames it
+    pub name: String,
+    pub mesh: Mesh,
+}
+
+// Every object in an OBJ file, as named meshes
+pub struct Model {
+    // The file name without its extension
+    pub name: String,
+    pub parts: Vec<Part>,
+}
+
+impl Model {
+    pub fn load(path: &str) -> Result<Model, ModelError> {
+        println!("Loading model {}...", path);
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load helicopter model");
+        let (models, materials) = tobj::load_obj(path, true)
+            .map_err(|error| ModelError::Load { path: path.to_string(), error })?;
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
 
-        for model in &models {
+        // Texture paths in MTL files are relative to the model
+        let directory = std::path::Path::new(path).parent().unwrap_or_else(|| std::path::Path::new(""));
+
+        let parts = models.into_iter().map(|model| {
             println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+            let mut material = model.mesh.material_id
+                .and_then(|id| materials.get(id))
+                .map_or_else(Material::default, Material::from);
+            let resolve = |texture: String| directory.join(texture).to_string_lossy().to_string();
+            material.diffuse_texture = material.diffuse_texture.map(resolve);
+            material.normal_texture = material.normal_texture.map(resolve);
+            let mut mesh = Mesh::from(model.mesh, [1.0, 1.0, 1.0, 1.0]);
+            mesh.material = material;
+            Part { name: model.name, mesh }
+        }).collect();
+
+        let name = std::path::Path::new(path).file_stem()
+            .map_or(path.to_string(), |stem| stem.to_string_lossy().to_string());
+        Ok(Model { name, parts })
+    }
+
+    // Loads a model that must have exactly the given objects, in any order. The objects are given as pairs of the
+    // name in the file and the name to give the part, since OBJ exporters tend to make up long names like
+    // "Main_Rotor_main_rotor" that scene files and node paths shouldn't have to repeat
+    pub fn load_expecting(path: &str, objects: &[(&str, &str)]) -> Result<Model, ModelError> {
+        let mut model = Model::load(path)?;
+        if let Some(&(missing, _)) = objects.iter().find(|&&(object, _)| model.part(object).is_none()) {
+            return Err(ModelError::MissingObject { path: path.to_string(), name: missing.to_string() });
+        }
+        let unexpected: Vec<String> = model.parts.iter()
+            .filter(|part| !objects.iter().any(|&(object, _)| object == part.name))
+            .map(|part| part.name.clone())
+            .collect();
+        if !unexpected.is_empty() {
+            return Err(ModelError::UnexpectedObjects { path: path.to_string(), names: unexpected });
+        }
+        for part in &mut model.parts {
+            if let Some(&(_, name)) = objects.iter().find(|&&(object, _)| object == part.name) {
+                part.name = name.to_string();
+            }
+        }
+        Ok(model)
+    }
+
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+
+    // The name scene nodes and scene files use for a part, "model/part"
+    pub fn mesh_name(&self, part: &Part) -> String {
+        format!("{}/{}", self.name, part.name)
+    }
+
+    // Adds a node named after the model below the parent, with a child for every part named after the part.
+    // make_mesh_node is given the mesh name of every part, and should return a node that draws it, the same way
+    // as when loading scene files. Returns None, and adds nothing, if it doesn't know one of the parts
+    pub fn instantiate<F>(&self, graph: &mut SceneGraph, parent: NodeId, make_mesh_node: F) -> Option<NodeId>
+        where F: Fn(&str) -> Option<SceneNode>
+    {
+        let part_nodes = self.parts.iter()
+            .map(|part| make_mesh_node(&self.mesh_name(part)).map(|node| node.with_name(&part.name)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let root = graph.add_node(SceneNode::new().with_name(&self.name));
+        graph.add_child(parent, root);
+        for node in part_nodes {
+            let node = graph.add_node(node);
+            graph.add_child(root, node);
+        }
+        Some(root)
+    }
+}
+
+// Size of generated terrain
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainScale {
+    // Distance between neighbouring height samples, along both x and z
+    pub horizontal: f32,
+    // Height of a sample of 1, which is white in a heightmap
+    pub vertical: f32,
+}
+
+// Fractal Perlin noise for terrain without a heightmap
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainNoise {
+    pub seed: u32,
+    // Noise cycles per sample, so lower is smoother
+    pub frequency: f32,
+    pub octaves: u32,
+    // How much each octave of detail is scaled down from the one before
+    pub persistence: f32,
+}
+
+pub struct Terrain;
+impl Terrain {
+    // Terrain models must have a single mesh
+    pub fn load(path: &str) -> Result<Mesh, ModelError> {
+        let mut model = Model::load(path)?;
+        match model.parts.len() {
+            1 => Ok(model.parts.remove(0).mesh),
+            0 => Err(ModelError::MissingObject { path: path.to_string(), name: "terrain".to_string() }),
+            _ => Err(ModelError::UnexpectedObjects {
+                path: path.to_string(),
+                names: model.parts.into_iter().skip(1).map(|part| part.name).collect(),
+            }),
+        }
+    }
+    // Terrain from a grayscale image, with a vertex for every pixel. Black is at height 0 and white at the vertical scale.
+    // The top row of the image is furthest along -z, like a map seen from above. 16 bit images keep their precision,
+    // since 256 steps make visible terraces on tall terrain
+    pub fn from_heightmap(path: &str, scale: &TerrainScale) -> Result<Mesh, ModelError> {
+        let heightmap = image::open(path)
+            .map_err(|error| ModelError::Image { path: path.to_string(), error })?;
+        // Color images are converted with the same weights as to_luma uses
+        let luma = |r: u16, g: u16, b: u16| (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) / 65535.0;
+        let (columns, rows, heights): (u32, u32, Vec<f32>) = match heightmap {
+            image::DynamicImage::ImageLuma16(image) =>
+                (image.width(), image.height(), image.pixels().map(|p| p[0] as f32 / 65535.0).collect()),
+            image::DynamicImage::ImageLumaA16(image) =>
+                (image.width(), image.height(), image.pixels().map(|p| p[0] as f32 / 65535.0).collect()),
+            image::DynamicImage::ImageRgb16(image) =>
+                (image.width(), image.height(), image.pixels().map(|p| luma(p[0], p[1], p[2])).collect()),
+            image::DynamicImage::ImageRgba16(image) =>
+                (image.width(), image.height(), image.pixels().map(|p| luma(p[0], p[1], p[2])).collect()),
+            image => {
+                let image = image.to_luma8();
+                (image.width(), image.height(), image.pixels().map(|p| p[0] as f32 / 255.0).collect())
+            }
+        };
+        if columns < 2 || rows < 2 {
+            return Err(ModelError::HeightmapTooSmall { path: path.to_string(), width: columns, height: rows });
+        }
+        Ok(Terrain::from_heights(columns as usize, rows as usize, &heights, scale))
+    }
+
+    // Terrain from noise, for when there is no heightmap. Heights are between 0 and 1 before scaling, like a heightmap
+    pub fn from_noise(columns: usize, rows: usize, noise: &TerrainNoise, scale: &TerrainScale) -> Mesh {
+        let perlin = crate::noise::Perlin::new(noise.seed);
+        let mut heights = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let value = perlin.fractal(column as f32 * noise.frequency, row as f32 * noise.frequency, noise.octaves, noise.persistence);
+                heights.push((0.5 + 0.5 * value).clamp(0.0, 1.0));
+            }
+        }
+        Terrain::from_heights(columns, rows, &heights, scale)
+    }
+
+    // Terrain centered on the origin from a grid of heights, row by row, where rows go along +z.
+    // Needs at least two rows and two columns
+    pub fn from_heights(columns: usize, rows: usize, heights: &[f32], scale: &TerrainScale) -> Mesh {
+        assert!(columns >= 2 && rows >= 2, "Terrain needs at least 2x2 heights");
+        assert_eq!(heights.len(), columns * rows, "Expected a height for every column in every row");
+
+        let height = |column: usize, row: usize| heights[row * columns + column] * scale.vertical;
+        let mut vertices = Vec::with_capacity(columns * rows * 3);
+        let mut normals = Vec::with_capacity(columns * rows * 3);
+        let mut texcoords = Vec::with_capacity(columns * rows * 2);
+        for row in 0..rows {
+            for column in 0..columns {
+                vertices.extend_from_slice(&[
+                    (column as f32 - (columns - 1) as f32 / 2.0) * scale.horizontal,
+                    height(column, row),
+                    (row as f32 - (rows - 1) as f32 / 2.0) * scale.horizontal,
+                ]);
+
+                // Central differences, or one sided at the edges
+                let (left, right) = (column.saturating_sub(1), (column + 1).min(columns - 1));
+                let (back, front) = (row.saturating_sub(1), (row + 1).min(rows - 1));
+                let slope_x = (height(right, row) - height(left, row)) / ((right - left) as f32 * scale.horizontal);
+                let slope_z = (height(column, front) - height(column, back)) / ((front - back) as f32 * scale.horizontal);
+                let normal = glm::normalize(&glm::vec3(-slope_x, 1.0, -slope_z));
+                normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+
+                texcoords.extend_from_slice(&[column as f32 / (columns - 1) as f32, 1.0 - row as f32 / (rows - 1) as f32]);
+            }
+        }
+
+        let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let index = |column: usize, row: usize| (row * columns + column) as u32;
+                let (a, b, c, d) = (index(column, row), index(column + 1, row), index(column + 1, row + 1), index(column, row + 1));
+                indices.extend_from_slice(&[a, d, c, a, c, b]);
+            }
+        }
+
+        Mesh::new(vertices, normals, texcoords, indices, [1.0, 1.0, 1.0, 1.0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two triangles as separate objects, with the kind of names exporters give them
+    const TWO_OBJECTS: &str = "\
+o Body_body
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+o Main_Rotor_main_rotor
+v 0 2 0
+v 1 2 0
+v 0 2 1
+f 4 5 6
+";
+
+    fn write_obj(file_name: &str) -> String {
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, TWO_OBJECTS).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn tangent(mesh: &Mesh, vertex: u32) -> glm::Vec4 {
+        glm::make_vec4(&mesh.tangents[vertex as usize * 4..vertex as usize * 4 + 4])
+    }
+
+    // A cube of size 2 with its normals thrown away, so they have to be generated again
+    fn cube_without_normals() -> Mesh {
+        let mut cube = primitives::cube(2.0, 1);
+        cube.normals.clear();
+        cube
+    }
+
+    // The normal of the triangle, from its winding
+    fn face_normal(mesh: &Mesh, triangle: &[u32]) -> glm::Vec3 {
+        let (a, b, c) = (mesh.position(triangle[0]), mesh.position(triangle[1]), mesh.position(triangle[2]));
+        glm::normalize(&glm::cross(&(b - a), &(c - a)))
+    }
+
+    #[test]
+    fn flat_normals_are_face_normals() {
+        let mut cube = cube_without_normals();
+        cube.generate_normals(NormalMode::Flat);
+        assert_eq!(cube.normals.len(), cube.vertices.len());
+        for triangle in cube.indices.chunks_exact(3) {
+            for &vertex in triangle {
+                assert!(glm::distance(&cube.normal(vertex), &face_normal(&cube, triangle)) < 1e-5);
+            }
         }
+        // The two triangles of each face still share their diagonal
+        assert_eq!(cube.vertices.len() / 3, 24);
+    }
 
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
+    #[test]
+    fn smooth_normals_keep_edges_sharper_than_the_crease_angle() {
+        let mut cube = cube_without_normals();
+        cube.generate_normals(NormalMode::Smooth { crease_angle: DEFAULT_CREASE_ANGLE });
+        // The faces meet at right angles, which is sharper than 60 degrees, so they stay flat
+        for triangle in cube.indices.chunks_exact(3) {
+            for &vertex in triangle {
+                assert!(glm::distance(&cube.normal(vertex), &face_normal(&cube, triangle)) < 1e-5);
+            }
+        }
 
-        Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
+        let mut cube = cube_without_normals();
+        cube.generate_normals(NormalMode::Smooth { crease_angle: std::f32::consts::PI });
+        // Every corner is averaged over the three faces that meet there, so the normal leans out of all of them
+        for vertex in 0..(cube.vertices.len() / 3) as u32 {
+            let (position, normal) = (cube.position(vertex), cube.normal(vertex));
+            assert!((normal.norm() - 1.0).abs() < 1e-5);
+            for axis in 0..3 {
+                assert!(normal[axis] * position[axis] > 0.1, "{} at {}", normal, position);
+            }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn smooth_normals_are_weighted_by_area() {
+        // A large triangle facing up and a sliver tilted 45 degrees towards x, sharing the corner at the origin
+        let vertices = vec![
+            0.0, 0.0, 0.0,
+            10.0, 0.0, 0.0,
+            0.0, 0.0, -10.0,
+            0.1, -0.1, 0.0,
+            0.0, 0.0, -0.1,
+        ];
+        let mut mesh = Mesh::new(vertices, vec![], vec![], vec![0, 1, 2, 0, 3, 4], [1.0, 1.0, 1.0, 1.0]);
+        mesh.generate_normals(NormalMode::Smooth { crease_angle: DEFAULT_CREASE_ANGLE });
+
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        let origin = glm::vec3(0.0, 0.0, 0.0);
+        for vertex in 0..(mesh.vertices.len() / 3) as u32 {
+            if mesh.position(vertex) == origin {
+                // Unweighted, the normal would lean halfway towards the sliver, 22.5 degrees
+                assert!(glm::dot(&mesh.normal(vertex), &up) > 1f32.to_radians().cos(), "{}", mesh.normal(vertex));
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_texture() {
+        let plane = primitives::plane(2.0, 2.0, 2, 2);
+        let vertex_count = plane.vertices.len() / 3;
+        assert_eq!(plane.tangents.len(), vertex_count * 4);
+        // u goes along x and v along -z, so the bitangent is cross(normal, tangent) = -z and nothing is mirrored
+        for vertex in 0..vertex_count as u32 {
+            assert!(glm::distance(&tangent(&plane, vertex), &glm::vec4(1.0, 0.0, 0.0, 1.0)) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn tangents_split_vertices_at_mirrored_seams() {
+        // Two triangles facing +z that share the edge at x = 0, with the texture mirrored across it
+        let vertices = vec![
+            0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            1.0, 0.0, 0.0,
+            -1.0, 0.0, 0.0,
+        ];
+        let normals = [0.0, 0.0, 1.0].repeat(4);
+        let texcoords = vec![0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0];
+        let mesh = Mesh::new(vertices, normals, texcoords, vec![0, 2, 1, 0, 1, 3], [1.0, 1.0, 1.0, 1.0]);
+
+        // The shared vertices are split, one copy for each side
+        assert_eq!(mesh.vertices.len() / 3, 6);
+        assert_eq!(mesh.tangents.len(), 6 * 4);
+        assert_eq!(mesh.colors.len(), 6 * 4);
+        for &vertex in &mesh.indices[0..3] {
+            assert!(glm::distance(&tangent(&mesh, vertex), &glm::vec4(1.0, 0.0, 0.0, 1.0)) < 1e-5);
+        }
+        for &vertex in &mesh.indices[3..6] {
+            assert!(glm::distance(&tangent(&mesh, vertex), &glm::vec4(-1.0, 0.0, 0.0, -1.0)) < 1e-5);
+        }
+        // The copies are at the same places as the originals
+        assert_eq!(mesh.position(mesh.indices[3]), mesh.position(mesh.indices[0]));
+        assert_eq!(mesh.position(mesh.indices[4]), mesh.position(mesh.indices[2]));
+    }
+
+    const UNIT_SCALE: TerrainScale = TerrainScale { horizontal: 1.0, vertical: 1.0 };
+
+    #[test]
+    fn tangents_are_only_averaged_across_shared_edges() {
+        // Two triangles facing +z that only touch at the origin, with the texture turned a quarter between them
+        let vertices = vec![
+            0.0, 0.0, 0.0,
+            1.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            -1.0, 0.0, 0.0,
+            0.0, -1.0, 0.0,
+        ];
+        let normals = [0.0, 0.0, 1.0].repeat(5);
+        let texcoords = vec![0.5, 0.5, 1.0, 0.5, 0.5, 1.0, 0.5, 1.0, 0.0, 0.5];
+        let mesh = Mesh::new(vertices, normals, texcoords, vec![0, 1, 2, 0, 3, 4], [1.0, 1.0, 1.0, 1.0]);
+
+        // Averaging them would give the diagonal, so the origin is split and each side keeps its own tangent
+        assert_eq!(mesh.vertices.len() / 3, 6);
+        assert_ne!(mesh.indices[0], mesh.indices[3]);
+        assert!(glm::distance(&tangent(&mesh, mesh.indices[0]), &glm::vec4(1.0, 0.0, 0.0, 1.0)) < 1e-5);
+        assert!(glm::distance(&tangent(&mesh, mesh.indices[3]), &glm::vec4(0.0, 1.0, 0.0, 1.0)) < 1e-5);
+    }
+
+    #[test]
+    fn tangents_are_weighted_by_corner_angle() {
+        // Two triangles facing +z that share the edge from the origin up the y axis. The first has a right angle
+        // at the origin and u along x, the second has half that angle and u along the diagonal
+        let vertices = vec![
+            0.0, 0.0, 0.0,
+            1.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            -1.0, 1.0, 0.0,
+        ];
+        let normals = [0.0, 0.0, 1.0].repeat(4);
+        let texcoords = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, -1.0, 2.0];
+        let mesh = Mesh::new(vertices, normals, texcoords, vec![0, 1, 2, 0, 2, 3], [1.0, 1.0, 1.0, 1.0]);
+
+        let right_angle = std::f32::consts::FRAC_PI_2;
+        let diagonal = glm::normalize(&glm::vec3(1.0, 1.0, 0.0));
+        let expected = glm::normalize(&(glm::vec3(1.0, 0.0, 0.0) * right_angle + diagonal * right_angle / 2.0));
+        assert_eq!(mesh.vertices.len() / 3, 4);
+        assert!(glm::distance(&tangent(&mesh, 0), &glm::vec4(expected.x, expected.y, expected.z, 1.0)) < 1e-5);
+    }
+
+    #[test]
+    fn terrain_grid_size_and_winding() {
+        let terrain = Terrain::from_heights(4, 3, &[0.0; 12], &TerrainScale { horizontal: 2.0, vertical: 1.0 });
+        assert_eq!(terrain.vertices.len() / 3, 12);
+        assert_eq!(terrain.index_count, 3 * 2 * 6);
+        // Centered on the origin, with 3 gaps of 2 along x and 2 gaps of 2 along z
+        assert_eq!(terrain.aabb.min, glm::vec3(-3.0, 0.0, -2.0));
+        assert_eq!(terrain.aabb.max, glm::vec3(3.0, 0.0, 2.0));
+        // Counter clockwise seen from above, so the triangles face up and aren't culled
+        for triangle in terrain.indices.chunks_exact(3) {
+            let (a, b, c) = (terrain.position(triangle[0]), terrain.position(triangle[1]), terrain.position(triangle[2]));
+            assert!(glm::cross(&(b - a), &(c - a)).y > 0.0);
+        }
+    }
+
+    #[test]
+    fn terrain_normals_on_a_slope() {
+        // Rising by 0.5 per column along +x, and by 0.25 per row along +z
+        let heights: Vec<f32> = (0..4).flat_map(|row| (0..5).map(move |column| 0.5 * column as f32 + 0.25 * row as f32)).collect();
+        let terrain = Terrain::from_heights(5, 4, &heights, &UNIT_SCALE);
+        let expected = glm::normalize(&glm::vec3(-0.5, 1.0, -0.25));
+        for vertex in 0..20 {
+            assert!(glm::distance(&terrain.normal(vertex), &expected) < 1e-5);
+        }
+        // Higher columns are further along +x, and higher rows further along +z
+        assert!(terrain.position(4).y > terrain.position(0).y && terrain.position(4).x > terrain.position(0).x);
+        assert!(terrain.position(15).y > terrain.position(0).y && terrain.position(15).z > terrain.position(0).z);
+    }
+
+    #[test]
+    fn noise_terrain_is_deterministic() {
+        let noise = TerrainNoise { seed: 42, frequency: 0.1, octaves: 4, persistence: 0.5 };
+        let a = Terrain::from_noise(16, 16, &noise, &UNIT_SCALE);
+        let b = Terrain::from_noise(16, 16, &noise, &UNIT_SCALE);
+        assert_eq!(a.vertices, b.vertices);
+        assert!(a.vertices.chunks_exact(3).all(|v| v[1] >= 0.0 && v[1] <= 1.0));
+        let c = Terrain::from_noise(16, 16, &TerrainNoise { seed: 43, ..noise }, &UNIT_SCALE);
+        assert_ne!(a.vertices, c.vertices);
+    }
+
+    #[test]
+    fn heightmaps_keep_16_bit_precision() {
+        let path = std::env::temp_dir().join("gloom_heightmap_test.png");
+        let pixels: Vec<u16> = vec![0, 1000, 1001, 65535];
+        image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_raw(2, 2, pixels).unwrap().save(&path).unwrap();
+        let terrain = Terrain::from_heightmap(&path.to_string_lossy(), &TerrainScale { horizontal: 1.0, vertical: 65535.0 }).unwrap();
+        let heights: Vec<f32> = terrain.vertices.chunks_exact(3).map(|v| v[1]).collect();
+        // 8 bits would have made the middle two the same
+        for (height, expected) in heights.iter().zip(&[0.0, 1000.0, 1001.0, 65535.0]) {
+            assert!((height - expected).abs() < 0.01, "{:?}", heights);
+        }
+    }
+
+    #[test]
+    fn heightmaps_smaller_than_2x2_are_an_error() {
+        let path = std::env::temp_dir().join("gloom_heightmap_too_small_test.png");
+        image::ImageBuffer::<image::Luma<u8>, Vec<u8>>::from_raw(1, 5, vec![0; 5]).unwrap().save(&path).unwrap();
+        match Terrain::from_heightmap(&path.to_string_lossy(), &TerrainScale { horizontal: 1.0, vertical: 1.0 }) {
+            Err(ModelError::HeightmapTooSmall { width: 1, height: 5, .. }) => {}
+            Err(e) => panic!("Wrong error: {}", e),
+            Ok(_) => panic!("Made terrain from a 1x5 heightmap"),
+        }
+    }
+
+    #[test]
+    fn load_expecting_renames_parts() {
+        let path = write_obj("gloom_rename_test.obj");
+        let model = Model::load_expecting(&path, &[("Body_body", "body"), ("Main_Rotor_main_rotor", "main_rotor")]).unwrap();
+        assert!(model.part("body").is_some());
+        let rotor = model.part("main_rotor").unwrap();
+        assert_eq!(model.mesh_name(rotor), "gloom_rename_test/main_rotor");
+
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let terrain = graph.add_node(SceneNode::new().with_name("terrain"));
+        graph.add_child(root, terrain);
+        let instance = model.instantiate(&mut graph, terrain, |name| Some(SceneNode::from_vao(1, 3).with_mesh_name(name))).unwrap();
+        graph[instance].name = Some("helicopter_3".to_string());
+        let rotor = graph.find("terrain/helicopter_3/main_rotor").expect("Part node not found by its path");
+        assert_eq!(graph[rotor].mesh_name.as_deref(), Some("gloom_rename_test/main_rotor"));
+    }
+
+    #[test]
+    fn load_expecting_reports_missing_and_unexpected_objects() {
+        let path = write_obj("gloom_expect_test.obj");
+        match Model::load_expecting(&path, &[("Body_body", "body"), ("Main_Rotor_main_rotor", "main_rotor"), ("Door_door", "door")]) {
+            Err(ModelError::MissingObject { name, .. }) => assert_eq!(name, "Door_door"),
+            other => panic!("Expected a missing object, got {:?}", other.map(|_| ())),
+        }
+        match Model::load_expecting(&path, &[("Body_body", "body")]) {
+            Err(ModelError::UnexpectedObjects { names, .. }) => assert_eq!(names, vec!["Main_Rotor_main_rotor".to_string()]),
+            other => panic!("Expected unexpected objects, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use gloom_common::primitives::{self, Primitive};
+
+use super::Mesh;
+
+// The shapes from gloom_common::primitives as meshes, for debug scenes and tests. They are white,
+// and get tangents and bounds like any other mesh. See there for what the parameters mean
+
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+fn mesh(primitive: Primitive) -> Mesh {
+    Mesh::new(primitive.vertices, primitive.normals, primitive.texcoords, primitive.indices, WHITE)
+}
+
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> Mesh {
+    mesh(primitives::plane(width, depth, columns, rows))
+}
+
+pub fn cube(size: f32, segments: u32) -> Mesh {
+    mesh(primitives::cube(size, segments))
+}
+
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    mesh(primitives::uv_sphere(radius, segments, rings))
+}
+
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    mesh(primitives::icosphere(radius, subdivisions))
+}
+
+pub fn disk(radius: f32, segments: u32) -> Mesh {
+    mesh(primitives::disk(radius, segments))
+}
+
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Mesh {
+    mesh(primitives::cylinder(radius, height, segments))
+}
+
+pub fn cone(radius: f32, height: f32, segments: u32) -> Mesh {
+    mesh(primitives::cone(radius, height, segments))
+}
+
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Mesh {
+    mesh(primitives::torus(major_radius, minor_radius, major_segments, minor_segments))
+}
@@ -0,0 +1,91 @@
+// Gradient noise, for generating terrain and other things that should look natural
+
+// Classic Perlin noise in two dimensions. The same seed always gives the same noise
+pub struct Perlin {
+    // Shuffled 0..256, twice, so lookups of a + 1 don't have to wrap
+    permutation: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u32) -> Perlin {
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        // Fisher-Yates with xorshift, so the shuffle doesn't depend on a random number crate
+        let mut state = seed.wrapping_mul(2654435761) | 1;
+        for i in (1..permutation.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            permutation.swap(i, state as usize % (i + 1));
+        }
+        let doubled = permutation.iter().chain(permutation.iter()).cloned().collect();
+        Perlin { permutation: doubled }
+    }
+
+    // Noise at the point, roughly between -1 and 1, and 0 at whole numbers
+    pub fn noise(&self, x: f32, y: f32) -> f32 {
+        let (cell_x, cell_y) = (x.floor(), y.floor());
+        let (x, y) = (x - cell_x, y - cell_y);
+        let (i, j) = ((cell_x as i32 & 255) as usize, (cell_y as i32 & 255) as usize);
+        let p = &self.permutation;
+
+        let gradient = |hash: u8, x: f32, y: f32| match hash & 7 {
+            0 => x + y,
+            1 => -x + y,
+            2 => x - y,
+            3 => -x - y,
+            4 => x,
+            5 => -x,
+            6 => y,
+            _ => -y,
+        };
+        let corner = |di: usize, dj: usize| gradient(p[p[i + di] as usize + j + dj], x - di as f32, y - dj as f32);
+
+        // Quintic fade, so the noise has a continuous second derivative at cell borders
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let (u, v) = (fade(x), fade(y));
+        lerp(lerp(corner(0, 0), corner(1, 0), u), lerp(corner(0, 1), corner(1, 1), u), v)
+    }
+
+    // Fractal noise: octaves layers of noise, each at twice the frequency and persistence times the amplitude of the one
+    // before. Scaled back to roughly between -1 and 1
+    pub fn fractal(&self, x: f32, y: f32, octaves: u32, persistence: f32) -> f32 {
+        let (mut sum, mut amplitude, mut frequency, mut total_amplitude) = (0.0, 1.0, 1.0, 0.0);
+        for _ in 0..octaves.max(1) {
+            sum += self.noise(x * frequency, y * frequency) * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= persistence;
+            frequency *= 2.0;
+        }
+        sum / total_amplitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_noise() {
+        let (a, b, other) = (Perlin::new(7), Perlin::new(7), Perlin::new(8));
+        let points = [(0.5, 0.25), (3.7, -1.2), (100.3, 42.9)];
+        for &(x, y) in &points {
+            assert_eq!(a.noise(x, y), b.noise(x, y));
+            assert_eq!(a.fractal(x, y, 5, 0.5), b.fractal(x, y, 5, 0.5));
+        }
+        assert!(points.iter().any(|&(x, y)| a.noise(x, y) != other.noise(x, y)));
+    }
+
+    #[test]
+    fn noise_is_zero_at_whole_numbers_and_bounded() {
+        let perlin = Perlin::new(1);
+        for i in -5..5 {
+            assert_eq!(perlin.noise(i as f32, (i * 3) as f32), 0.0);
+        }
+        for i in 0..1000 {
+            let (x, y) = (i as f32 * 0.137, i as f32 * 0.071);
+            assert!(perlin.noise(x, y).abs() <= 1.0);
+            assert!(perlin.fractal(x, y, 4, 0.5).abs() <= 1.0);
+        }
+    }
+}
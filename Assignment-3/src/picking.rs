@@ -0,0 +1,274 @@
+extern crate nalgebra_glm as glm;
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::mesh::Mesh;
+use crate::scene_graph::{NodeId, SceneGraph, SceneNode};
+
+// A ray in world space. The direction is normalized, so distances along the ray are in world units
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: glm::Vec3,
+    pub direction: glm::Vec3,
+}
+
+pub struct Hit {
+    pub node: NodeId,
+    // Index of the triangle in the mesh, so its vertices are indices[3*triangle..3*triangle+3]
+    pub triangle: usize,
+    pub distance: f32,
+    pub point: glm::Vec3,
+}
+
+impl Ray {
+    // Turns a cursor position into a ray going from the near plane into the scene.
+    // The cursor position is in pixels from the top left corner, like the window events report it
+    pub fn from_cursor(cursor_x: f32, cursor_y: f32, width: f32, height: f32, view_projection: &glm::Mat4) -> Ray {
+        let inverse = glm::inverse(view_projection);
+        let x = 2.0 * cursor_x / width - 1.0;
+        let y = 1.0 - 2.0 * cursor_y / height;
+        let unproject = |z: f32| {
+            let point = inverse * glm::vec4(x, y, z, 1.0);
+            point.xyz() / point.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        Ray { origin: near, direction: glm::normalize(&(far - near)) }
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // Distance to where the ray enters the box, or 0 if it starts inside it
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::INFINITY;
+        for i in 0..3 {
+            // Division by zero gives infinities that work out for rays parallel to a slab
+            let inverse_direction = 1.0 / self.direction[i];
+            let t0 = (aabb.min[i] - self.origin[i]) * inverse_direction;
+            let t1 = (aabb.max[i] - self.origin[i]) * inverse_direction;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        if near <= far { Some(near) } else { None }
+    }
+
+    pub fn intersect_sphere(&self, sphere: &BoundingSphere) -> Option<f32> {
+        let offset = self.origin - sphere.center;
+        let b = glm::dot(&offset, &self.direction);
+        let c = glm::dot(&offset, &offset) - sphere.radius * sphere.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let far = -b + discriminant.sqrt();
+        if far < 0.0 {
+            return None;
+        }
+        Some((-b - discriminant.sqrt()).max(0.0))
+    }
+
+    // Möller-Trumbore, hits from both sides of the triangle count
+    pub fn intersect_triangle(&self, a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> Option<f32> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = glm::cross(&self.direction, &edge2);
+        let determinant = glm::dot(&edge1, &p);
+        if determinant.abs() < 1e-8 {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+        let offset = self.origin - a;
+        let u = glm::dot(&offset, &p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = glm::cross(&offset, &edge1);
+        let v = glm::dot(&self.direction, &q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = glm::dot(&edge2, &q) * inverse_determinant;
+        if distance >= 0.0 { Some(distance) } else { None }
+    }
+}
+
+// Finds the closest triangle hit by the ray among the visible nodes in the given layers, below and including start.
+// mesh_of gives the mesh a node draws, which is needed since nodes only know the VAO it was uploaded to.
+// Subtrees whose world bounds the ray misses, or that are further away than the closest hit so far, are skipped
+pub fn pick<'a, F>(graph: &SceneGraph, start: NodeId, ray: &Ray, layers: u32, mesh_of: F) -> Option<Hit>
+    where F: Fn(&SceneNode) -> Option<&'a Mesh>
+{
+    let mut closest: Option<Hit> = None;
+    let mut stack = vec![start];
+    while let Some(id) = stack.pop() {
+        let node = &graph[id];
+        if !node.visible() {
+            continue;
+        }
+        let closest_distance = closest.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+        let entry = match (&node.world_bounding_sphere, &node.world_aabb) {
+            (Some(sphere), Some(aabb)) => ray.intersect_sphere(sphere).and(ray.intersect_aabb(aabb)),
+            // Unbounded subtrees can't be skipped
+            _ => Some(0.0),
+        };
+        match entry {
+            Some(distance) if distance <= closest_distance => {}
+            _ => continue,
+        }
+        stack.extend(node.children());
+
+        if node.index_count() <= 0 || !node.in_layers(layers) {
+            continue;
+        }
+        if let Some(mesh) = mesh_of(node) {
+            if let Some(hit) = pick_mesh(id, node, mesh, ray) {
+                if hit.distance < closest_distance {
+                    closest = Some(hit);
+                }
+            }
+        }
+    }
+    closest
+}
+
+fn pick_mesh(id: NodeId, node: &SceneNode, mesh: &Mesh, ray: &Ray) -> Option<Hit> {
+    let world_vertices: Vec<glm::Vec3> = mesh.vertices.chunks_exact(3)
+        .map(|v| node.transform_point(&glm::vec3(v[0], v[1], v[2])))
+        .collect();
+
+    let mut closest: Option<Hit> = None;
+    for (triangle, indices) in mesh.indices.chunks_exact(3).enumerate() {
+        let a = &world_vertices[indices[0] as usize];
+        let b = &world_vertices[indices[1] as usize];
+        let c = &world_vertices[indices[2] as usize];
+        if let Some(distance) = ray.intersect_triangle(a, b, c) {
+            if closest.as_ref().is_none_or(|hit| distance < hit.distance) {
+                closest = Some(Hit { node: id, triangle, distance, point: ray.at(distance) });
+            }
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::primitives;
+    use crate::scene_graph::LAYER_ALL;
+
+    fn ray(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+        Ray { origin, direction: glm::normalize(&direction) }
+    }
+
+    fn assert_distance(distance: Option<f32>, expected: f32) {
+        match distance {
+            Some(distance) => assert!((distance - expected).abs() < 1e-4, "{} != {}", distance, expected),
+            None => panic!("Missed, expected a hit at {}", expected),
+        }
+    }
+
+    #[test]
+    fn centre_of_the_screen_is_the_camera_forward_ray() {
+        let eye = glm::vec3(1.0, 2.0, 5.0);
+        let target = glm::vec3(-2.0, 0.0, -3.0);
+        let view = glm::look_at(&eye, &target, &glm::vec3(0.0, 1.0, 0.0));
+        let near = 0.5;
+        let projection = glm::perspective(1.0, 1.0, near, 100.0);
+
+        let ray = Ray::from_cursor(300.0, 300.0, 600.0, 600.0, &(projection * view));
+        let forward = glm::normalize(&(target - eye));
+        assert!(glm::distance(&ray.direction, &forward) < 1e-4);
+        // The ray starts on the near plane
+        assert!(glm::distance(&ray.origin, &(eye + forward * near)) < 1e-3);
+
+        // The top of the window is up, and the left of the window is left
+        let up = Ray::from_cursor(300.0, 0.0, 600.0, 600.0, &(projection * view));
+        assert!(up.direction.y > ray.direction.y);
+        let left = Ray::from_cursor(0.0, 300.0, 600.0, 600.0, &(projection * view));
+        let right = glm::cross(&forward, &glm::vec3(0.0, 1.0, 0.0));
+        assert!(glm::dot(&left.direction, &right) < 0.0);
+    }
+
+    #[test]
+    fn aabb_intersection() {
+        let aabb = Aabb { min: glm::vec3(0.0, 0.0, 0.0), max: glm::vec3(1.0, 1.0, 1.0) };
+        assert_distance(ray(glm::vec3(-5.0, 0.5, 0.5), glm::vec3(1.0, 0.0, 0.0)).intersect_aabb(&aabb), 5.0);
+        assert_distance(ray(glm::vec3(0.5, 0.5, 0.5), glm::vec3(0.0, -1.0, 0.0)).intersect_aabb(&aabb), 0.0);
+        assert!(ray(glm::vec3(-5.0, 0.5, 0.5), glm::vec3(-1.0, 0.0, 0.0)).intersect_aabb(&aabb).is_none());
+        // Parallel to the x slab, but above the box
+        assert!(ray(glm::vec3(-5.0, 2.0, 0.5), glm::vec3(1.0, 0.0, 0.0)).intersect_aabb(&aabb).is_none());
+        assert!(ray(glm::vec3(-5.0, 0.5, 0.5), glm::vec3(1.0, 1.0, 0.0)).intersect_aabb(&aabb).is_none());
+    }
+
+    #[test]
+    fn sphere_intersection() {
+        let sphere = BoundingSphere { center: glm::vec3(0.0, 0.0, -10.0), radius: 2.0 };
+        assert_distance(ray(glm::zero(), glm::vec3(0.0, 0.0, -1.0)).intersect_sphere(&sphere), 8.0);
+        assert_distance(ray(glm::vec3(0.0, 1.0, -10.0), glm::vec3(1.0, 0.0, 0.0)).intersect_sphere(&sphere), 0.0);
+        assert!(ray(glm::zero(), glm::vec3(0.0, 0.0, 1.0)).intersect_sphere(&sphere).is_none());
+        assert!(ray(glm::vec3(0.0, 3.0, 0.0), glm::vec3(0.0, 0.0, -1.0)).intersect_sphere(&sphere).is_none());
+    }
+
+    #[test]
+    fn triangle_intersection() {
+        let (a, b, c) = (glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0));
+        assert_distance(ray(glm::vec3(0.25, 0.25, 1.0), glm::vec3(0.0, 0.0, -1.0)).intersect_triangle(&a, &b, &c), 1.0);
+        // From behind
+        assert_distance(ray(glm::vec3(0.25, 0.25, -2.0), glm::vec3(0.0, 0.0, 1.0)).intersect_triangle(&a, &b, &c), 2.0);
+        // Misses past the long edge, and points away
+        assert!(ray(glm::vec3(0.75, 0.75, 1.0), glm::vec3(0.0, 0.0, -1.0)).intersect_triangle(&a, &b, &c).is_none());
+        assert!(ray(glm::vec3(0.25, 0.25, 1.0), glm::vec3(0.0, 0.0, 1.0)).intersect_triangle(&a, &b, &c).is_none());
+        // Parallel to the triangle's plane, both in it and beside it
+        assert!(ray(glm::vec3(-1.0, 0.25, 0.0), glm::vec3(1.0, 0.0, 0.0)).intersect_triangle(&a, &b, &c).is_none());
+        assert!(ray(glm::vec3(-1.0, 0.25, 1.0), glm::vec3(1.0, 0.0, 0.0)).intersect_triangle(&a, &b, &c).is_none());
+    }
+
+    #[test]
+    fn pick_finds_the_nearest_of_overlapping_nodes() {
+        let plane = primitives::plane(4.0, 4.0, 1, 1);
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        // The far node is added first, so the nearer one can't win just by being found first
+        let far = graph.add_node(SceneNode::from_mesh(1, &plane).with_name("far"));
+        let near = graph.add_node(SceneNode::from_mesh(1, &plane).with_name("near"));
+        graph[far].set_position(glm::vec3(0.0, 1.0, 0.0));
+        graph[near].set_position(glm::vec3(0.5, 3.0, 0.0));
+        graph.add_child(root, far);
+        graph.add_child(root, near);
+        graph.update_transformations();
+
+        let down = ray(glm::vec3(0.5, 10.0, 0.5), glm::vec3(0.0, -1.0, 0.0));
+        let hit = pick(&graph, root, &down, LAYER_ALL, |_| Some(&plane)).expect("Missed both planes");
+        assert_eq!(hit.node, near);
+        assert!((hit.distance - 7.0).abs() < 1e-4);
+        assert!(glm::distance(&hit.point, &glm::vec3(0.5, 3.0, 0.5)) < 1e-4);
+
+        // Hidden nodes can't be picked
+        graph[near].set_visible(false);
+        let hit = pick(&graph, root, &down, LAYER_ALL, |_| Some(&plane)).expect("Missed the far plane");
+        assert_eq!(hit.node, far);
+
+        let beside = ray(glm::vec3(5.0, 10.0, 0.0), glm::vec3(0.0, -1.0, 0.0));
+        assert!(pick(&graph, root, &beside, LAYER_ALL, |_| Some(&plane)).is_none());
+    }
+
+    #[test]
+    fn pick_finds_nodes_without_bounds() {
+        let plane = primitives::plane(4.0, 4.0, 1, 1);
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let bounded = graph.add_node(SceneNode::from_mesh(1, &plane));
+        // The mesh is known, but the node has no bounds, so it is outside its parent's bounds as far as they know
+        let unbounded = graph.add_node(SceneNode::from_vao(1, plane.index_count));
+        graph[unbounded].set_position(glm::vec3(10.0, 0.0, 0.0));
+        graph.add_child(root, bounded);
+        graph.add_child(bounded, unbounded);
+        graph.update_transformations();
+
+        let down = ray(glm::vec3(10.5, 10.0, 0.5), glm::vec3(0.0, -1.0, 0.0));
+        let hit = pick(&graph, root, &down, LAYER_ALL, |_| Some(&plane)).expect("Missed the unbounded node");
+        assert_eq!(hit.node, unbounded);
+    }
+}
@@ -0,0 +1,377 @@
+extern crate nalgebra_glm as glm;
+
+use std::fmt::Write;
+
+use crate::scene_graph::{EulerOrder, NodeId, SceneGraph, SceneNode};
+
+// Reads and writes scene graphs as text files that are meant to be edited by hand. A scene file looks like this:
+//
+//     # Comments start with a hash
+//     node "terrain" {
+//         mesh "terrain"
+//         position 0 0 0
+//         node "helicopter_0" {
+//             tags "helicopter"
+//             rotation 0 3.14 0
+//             node "tail_rotor" {
+//                 mesh "helicopter/tail_rotor"
+//                 reference 0.35 2.3 10.4
+//                 color 0.1 0.3 0.1 1
+//             }
+//         }
+//     }
+//
+// Node names are optional, an unnamed node is written as just node {. Inside quotes a backslash escapes the next
+// character, so \" and \\ give a quote and a backslash, and \n gives a line break.
+// Every property is optional, and falls back to the default of SceneNode::new. The properties are
+//     mesh "name"             The mesh drawn by the node. Mesh names are resolved by the caller when loading
+//     position x y z
+//     rotation x y z          Euler angles in radians
+//     order XYZ               The order of the Euler angles, one of XYZ, XZY, YXZ, YZX, ZXY and ZYX
+//     orientation x y z w     A quaternion, used instead of the Euler angles
+//     scale x y z
+//     reference x y z         The reference point that rotation and scaling happens about
+//     color r g b a           Multiplied with the colors of the mesh
+//     tags "a" "b" ...
+//     layers mask             The render layer bitmask
+//     visible true|false
+
+struct Token {
+    text: String,
+    quoted: bool,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '{' || c == '}' {
+                chars.next();
+                tokens.push(Token { text: c.to_string(), quoted: false, line: line_number });
+            } else if c == '"' {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some(c @ '"') | Some(c @ '\\') => text.push(c),
+                            Some(c) => return Err(format!("Line {}: Unknown escape '\\{}'", line_number, c)),
+                            None => return Err(format!("Line {}: Unterminated string", line_number)),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(format!("Line {}: Unterminated string", line_number)),
+                    }
+                }
+                tokens.push(Token { text, quoted: true, line: line_number });
+            } else {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == '"' || c == '#' { break }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token { text, quoted: false, line: line_number });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a, F: FnMut(&str) -> Option<SceneNode>> {
+    tokens: Vec<Token>,
+    position: usize,
+    graph: &'a mut SceneGraph,
+    make_mesh_node: F,
+}
+
+impl<'a, F: FnMut(&str) -> Option<SceneNode>> Parser<'a, F> {
+    fn next(&mut self) -> Result<&Token, String> {
+        let last_line = self.tokens.last().map_or(0, |token| token.line);
+        let token = self.tokens.get(self.position)
+            .ok_or_else(|| format!("Line {}: Unexpected end of file", last_line))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.quoted || token.text != text {
+            return Err(format!("Line {}: Expected '{}', found '{}'", token.line, text, token.text));
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        if !token.quoted {
+            return Err(format!("Line {}: Expected a quoted string, found '{}'", token.line, token.text));
+        }
+        Ok(token.text.clone())
+    }
+
+    fn float(&mut self) -> Result<f32, String> {
+        let token = self.next()?;
+        token.text.parse::<f32>()
+            .map_err(|_| format!("Line {}: Expected a number, found '{}'", token.line, token.text))
+    }
+
+    fn vec3(&mut self) -> Result<glm::Vec3, String> {
+        Ok(glm::vec3(self.float()?, self.float()?, self.float()?))
+    }
+
+    fn vec4(&mut self) -> Result<glm::Vec4, String> {
+        Ok(glm::vec4(self.float()?, self.float()?, self.float()?, self.float()?))
+    }
+
+    fn is_at(&self, text: &str) -> bool {
+        self.tokens.get(self.position).is_some_and(|token| !token.quoted && token.text == text)
+    }
+
+    // Parses a node, which has already had its "node" keyword consumed, and adds it to the graph below the parent
+    fn node(&mut self, parent: NodeId) -> Result<NodeId, String> {
+        let name = if self.is_at("{") { None } else { Some(self.string()?) };
+        self.expect("{")?;
+        let mut node = SceneNode::new();
+        node.name = name;
+        let id = self.graph.add_node(node);
+        self.graph.add_child(parent, id);
+
+        while !self.is_at("}") {
+            let key = self.next()?;
+            let (key, line) = (key.text.clone(), key.line);
+            match key.as_str() {
+                "node" => { self.node(id)?; }
+                "mesh" => {
+                    let mesh_name = self.string()?;
+                    let mesh_node = (self.make_mesh_node)(&mesh_name)
+                        .ok_or_else(|| format!("Line {}: Unknown mesh '{}'", line, mesh_name))?;
+                    let node = &mut self.graph[id];
+                    node.vao_id = mesh_node.vao_id;
+                    node.set_index_count(mesh_node.index_count());
+                    node.set_mesh_aabb(mesh_node.mesh_aabb());
+                    node.set_mesh_bounding_sphere(mesh_node.mesh_bounding_sphere());
+                    node.material = mesh_node.material;
+                    node.diffuse_texture = mesh_node.diffuse_texture;
+                    node.normal_texture = mesh_node.normal_texture;
+                    node.mesh_name = Some(mesh_name);
+                }
+                "position" => { let v = self.vec3()?; self.graph[id].set_position(v); }
+                "rotation" => { let v = self.vec3()?; self.graph[id].set_rotation(v); }
+                "order" => {
+                    let order = self.next()?;
+                    let order = match order.text.as_str() {
+                        "XYZ" => EulerOrder::XYZ,
+                        "XZY" => EulerOrder::XZY,
+                        "YXZ" => EulerOrder::YXZ,
+                        "YZX" => EulerOrder::YZX,
+                        "ZXY" => EulerOrder::ZXY,
+                        "ZYX" => EulerOrder::ZYX,
+                        other => return Err(format!("Line {}: Unknown rotation order '{}'", order.line, other)),
+                    };
+                    self.graph[id].set_rotation_order(order);
+                }
+                "orientation" => {
+                    let q = self.vec4()?;
+                    self.graph[id].set_orientation(Some(glm::quat(q.x, q.y, q.z, q.w)));
+                }
+                "scale" => { let v = self.vec3()?; self.graph[id].set_scale(v); }
+                "reference" => { let v = self.vec3()?; self.graph[id].set_reference_point(v); }
+                "color" => { self.graph[id].color = self.vec4()?; }
+                "tags" => {
+                    while self.tokens.get(self.position).is_some_and(|token| token.quoted) {
+                        let tag = self.string()?;
+                        self.graph[id].tags.push(tag);
+                    }
+                }
+                "layers" => {
+                    let layers = self.next()?;
+                    let layers = layers.text.parse()
+                        .map_err(|_| format!("Line {}: Expected a layer mask, found '{}'", layers.line, layers.text))?;
+                    self.graph[id].set_layers(layers);
+                }
+                "visible" => {
+                    let visible = self.next()?;
+                    let visible = match visible.text.as_str() {
+                        "true" => true,
+                        "false" => false,
+                        other => return Err(format!("Line {}: Expected true or false, found '{}'", visible.line, other)),
+                    };
+                    self.graph[id].set_visible(visible);
+                }
+                other => return Err(format!("Line {}: Unknown property '{}'", line, other)),
+            }
+        }
+        self.expect("}")?;
+        Ok(id)
+    }
+}
+
+// Adds the nodes in the scene source below the given parent, and returns the top level nodes.
+// make_mesh_node is called with every mesh name in the file, and should return a node that draws that mesh,
+// typically one made with SceneNode::from_mesh
+pub fn parse<F>(source: &str, graph: &mut SceneGraph, parent: NodeId, make_mesh_node: F) -> Result<Vec<NodeId>, String>
+    where F: FnMut(&str) -> Option<SceneNode>
+{
+    let tokens = tokenize(source)?;
+    let existing_children = graph[parent].children().len();
+    let mut parser = Parser { tokens, position: 0, graph, make_mesh_node };
+    let mut nodes = vec![];
+    let mut result = Ok(());
+    while result.is_ok() && parser.position < parser.tokens.len() {
+        result = parser.expect("node").and_then(|_| parser.node(parent)).map(|id| nodes.push(id));
+    }
+
+    // Don't leave a half loaded scene behind
+    if let Err(e) = result {
+        let added = graph[parent].children()[existing_children..].to_vec();
+        for id in added {
+            graph.remove(id);
+        }
+        return Err(e);
+    }
+    Ok(nodes)
+}
+
+pub fn load<F>(path: &str, graph: &mut SceneGraph, parent: NodeId, make_mesh_node: F) -> Result<Vec<NodeId>, String>
+    where F: FnMut(&str) -> Option<SceneNode>
+{
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read scene file {}: {}", path, e))?;
+    parse(&source, graph, parent, make_mesh_node).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Puts the text in quotes, escaping what the tokenizer would otherwise read as the end of the string or the line
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn write_node(out: &mut String, graph: &SceneGraph, id: NodeId, depth: usize) {
+    let node = &graph[id];
+    let indent = "    ".repeat(depth);
+    let vec3 = |v: glm::Vec3| format!("{} {} {}", v.x, v.y, v.z);
+
+    // Writing to a String can not fail
+    match &node.name {
+        Some(name) => { let _ = writeln!(out, "{}node {} {{", indent, quote(name)); }
+        None => { let _ = writeln!(out, "{}node {{", indent); }
+    }
+    if let Some(mesh_name) = &node.mesh_name {
+        let _ = writeln!(out, "{}    mesh {}", indent, quote(mesh_name));
+    }
+    let _ = writeln!(out, "{}    position {}", indent, vec3(node.position()));
+    match node.orientation() {
+        Some(q) => { let _ = writeln!(out, "{}    orientation {} {} {} {}", indent, q.i, q.j, q.k, q.w); }
+        None => {
+            let _ = writeln!(out, "{}    rotation {}", indent, vec3(node.rotation()));
+            let _ = writeln!(out, "{}    order {:?}", indent, node.rotation_order());
+        }
+    }
+    let _ = writeln!(out, "{}    scale {}", indent, vec3(node.scale()));
+    let _ = writeln!(out, "{}    reference {}", indent, vec3(node.reference_point()));
+    let c = node.color;
+    let _ = writeln!(out, "{}    color {} {} {} {}", indent, c.x, c.y, c.z, c.w);
+    if !node.tags.is_empty() {
+        let tags: Vec<_> = node.tags.iter().map(|tag| quote(tag)).collect();
+        let _ = writeln!(out, "{}    tags {}", indent, tags.join(" "));
+    }
+    let _ = writeln!(out, "{}    layers {}", indent, node.layers());
+    let _ = writeln!(out, "{}    visible {}", indent, node.visible());
+    for &child in node.children() {
+        write_node(out, graph, child, depth + 1);
+    }
+    let _ = writeln!(out, "{}}}", indent);
+}
+
+// Writes the children of the given node, and everything below them. The node itself is not written,
+// so saving the root and loading the file back in below a fresh root gives the same scene
+pub fn to_string(graph: &SceneGraph, id: NodeId) -> String {
+    let mut out = String::new();
+    for &child in graph[id].children() {
+        write_node(&mut out, graph, child, 0);
+    }
+    out
+}
+
+pub fn save(path: &str, graph: &SceneGraph, id: NodeId) -> Result<(), String> {
+    std::fs::write(path, to_string(graph, id))
+        .map_err(|e| format!("Failed to write scene file {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh_node(name: &str) -> Option<SceneNode> {
+        match name {
+            "missing" => None,
+            _ => Some(SceneNode::from_vao(1, 3)),
+        }
+    }
+
+    #[test]
+    fn save_parse_save_round_trips() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let terrain = graph.add_node(SceneNode::from_vao(1, 3).with_mesh_name("terrain").with_name("terrain"));
+        let unnamed = graph.add_node(SceneNode::new().with_tag("plain").with_tag("say \"hi\""));
+        let awkward = graph.add_node(SceneNode::new().with_name("back\\slash \"quoted\"\nnext line # not a comment {}"));
+        graph[unnamed].set_rotation(glm::vec3(0.5, -1.25, 3.0));
+        graph[unnamed].set_rotation_order(EulerOrder::ZYX);
+        graph[unnamed].set_layers(6);
+        graph[awkward].set_orientation(Some(glm::quat(0.0, 0.6, 0.0, 0.8)));
+        graph[awkward].set_scale(glm::vec3(1.0, 2.0, 0.5));
+        graph[awkward].set_visible(false);
+        graph[awkward].color = glm::vec4(0.1, 0.2, 0.3, 0.4);
+        graph.add_child(root, terrain);
+        graph.add_child(terrain, unnamed);
+        graph.add_child(unnamed, awkward);
+
+        let saved = to_string(&graph, root);
+        let mut loaded = SceneGraph::new();
+        let loaded_root = loaded.root();
+        parse(&saved, &mut loaded, loaded_root, mesh_node).unwrap();
+        assert_eq!(to_string(&loaded, loaded_root), saved);
+
+        let unnamed = loaded.find("terrain").map(|terrain| loaded[terrain].children()[0]).unwrap();
+        assert_eq!(loaded[unnamed].name, None);
+        assert_eq!(loaded[unnamed].tags, vec!["plain".to_string(), "say \"hi\"".to_string()]);
+        let awkward = loaded[unnamed].children()[0];
+        assert_eq!(loaded[awkward].name.as_deref(), Some("back\\slash \"quoted\"\nnext line # not a comment {}"));
+    }
+
+    #[test]
+    fn failed_parse_leaves_graph_unchanged() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let source = "node \"a\" { node \"b\" { mesh \"missing\" } }";
+        assert!(parse(source, &mut graph, root, mesh_node).unwrap_err().contains("Unknown mesh 'missing'"));
+        assert!(graph[root].children().is_empty());
+    }
+
+    #[test]
+    fn unknown_escape_is_an_error() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        assert!(parse("node \"a\\tb\" {}", &mut graph, root, mesh_node).unwrap_err().contains("Unknown escape"));
+    }
+}
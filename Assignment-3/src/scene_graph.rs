@@ -1,58 +1,473 @@
 extern crate nalgebra_glm as glm;
 
-use std::mem::ManuallyDrop;
-use std::pin::Pin;
-
-// Used to crete an unholy abomination upon which you should not cast your gaze.
-// This ended up being a necessity due to wanting to keep the code written by students as "straight forward" as possible
-// It is very very double plus ungood Rust, and intentionally leaks memory like a sieve. But it works, and you're more than welcome to pretend it doesn't exist!
-// In case you're curious about how it works; It allocates memory on the heap (Box), promises to prevent it from being moved or deallocated until dropped (Pin) 
-// and finally prevents the compiler from dropping it automatically at all (ManuallyDrop). If that sounds like a janky solution, it's because it is.
-// Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of having what I arbitrarily decided to be the required level of
-// simplicity of use.
-pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+use std::ops::{Index, IndexMut};
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::mesh::{Material, Mesh};
+
+// The scene graph owns every node in a flat arena, and nodes refer to each other through NodeIds.
+// This keeps the graph free of raw pointers: nodes are freed when they are removed or when the graph is dropped,
+// and traversals only ever borrow the graph.
+// A NodeId carries the generation of the slot it was handed out for, so an id to a node that has since been
+// removed will never silently point to whatever node reuses its slot.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+// Render layers are bits in a mask. A node is drawn by a render pass if they share at least one layer
+pub const LAYER_DEFAULT: u32 = 1 << 0;
+pub const LAYER_DEBUG: u32 = 1 << 1;
+pub const LAYER_ALL: u32 = !0;
+
+// The order the rotations about each axis are multiplied in. XYZ is rotate_x * rotate_y * rotate_z,
+// which means the z rotation is applied to the model first and the x rotation last
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EulerOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+fn axis_rotation(axis: usize, angle: f32) -> glm::Mat3 {
+    let (s, c) = angle.sin_cos();
+    match axis {
+        0 => glm::mat3(
+            1.0, 0.0, 0.0,
+            0.0, c, -s,
+            0.0, s, c,
+        ),
+        1 => glm::mat3(
+            c, 0.0, s,
+            0.0, 1.0, 0.0,
+            -s, 0.0, c,
+        ),
+        _ => glm::mat3(
+            c, -s, 0.0,
+            s, c, 0.0,
+            0.0, 0.0, 1.0,
+        ),
+    }
+}
+
+// Some unit vector at a right angle to the given unit vector
+fn perpendicular(v: &glm::Vec3) -> glm::Vec3 {
+    let other = if v.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+    glm::normalize(&glm::cross(v, &other))
+}
+
+impl EulerOrder {
+    // The axes in multiplication order, 0 = x, 1 = y and 2 = z
+    fn axes(self) -> [usize; 3] {
+        match self {
+            EulerOrder::XYZ => [0, 1, 2],
+            EulerOrder::XZY => [0, 2, 1],
+            EulerOrder::YXZ => [1, 0, 2],
+            EulerOrder::YZX => [1, 2, 0],
+            EulerOrder::ZXY => [2, 0, 1],
+            EulerOrder::ZYX => [2, 1, 0],
+        }
+    }
+
+    pub fn to_mat3(self, angles: &glm::Vec3) -> glm::Mat3 {
+        let [i, j, k] = self.axes();
+        axis_rotation(i, angles[i]) * axis_rotation(j, angles[j]) * axis_rotation(k, angles[k])
+    }
+
+    pub fn to_quat(self, angles: &glm::Vec3) -> glm::Quat {
+        let unit = [glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)];
+        let axis = |a: usize| glm::quat_angle_axis(angles[a], &unit[a]);
+        let [i, j, k] = self.axes();
+        axis(i) * axis(j) * axis(k)
+    }
+
+    // Finds the angles that give the rotation matrix when multiplied in this order
+    pub fn angles_from_mat3(self, rotation: &glm::Mat3) -> glm::Vec3 {
+        let [i, j, k] = self.axes();
+        let r = |row: usize, col: usize| rotation[(row, col)];
+        // The cyclic orders (XYZ, YZX, ZXY) and the others differ in the signs of the sines
+        let sign = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+
+        let mut angles = glm::Vec3::zeros();
+        angles[j] = (sign * r(i, k)).clamp(-1.0, 1.0).asin();
+        if r(i, k).abs() < 0.9999 {
+            angles[i] = (-sign * r(j, k)).atan2(r(k, k));
+            angles[k] = (-sign * r(i, j)).atan2(r(i, i));
+        } else {
+            // Gimbal lock, only the sum of the outer and inner rotation is known, so put all of it in the outer one
+            angles[i] = (sign * r(k, j)).atan2(r(j, j));
+        }
+        angles
+    }
+}
 
 pub struct SceneNode {
-    pub position: glm::Vec3,
-    pub rotation: glm::Vec3,
-    pub scale: glm::Vec3,
-    pub reference_point: glm::Vec3,
+    // The transform is only reachable through setters, so that changing it marks the node as dirty
+    position: glm::Vec3,
+    // Euler angles in radians, applied in rotation_order. Ignored when the node has an orientation
+    rotation: glm::Vec3,
+    rotation_order: EulerOrder,
+    orientation: Option<glm::Quat>,
+    scale: glm::Vec3,
+    reference_point: glm::Vec3,
 
+    // Set when the transform, or the node's place in the graph, has changed since current_transformation_matrix was computed
+    dirty: bool,
     pub current_transformation_matrix: glm::Mat4,
 
     pub vao_id: u32,
-    pub index_count: i32,
+    // Only reachable through setters, like the transform, since it decides whether the node is counted as drawn
+    index_count: i32,
+    // Identifies the mesh in scene files, see scene_file.rs
+    pub mesh_name: Option<String>,
+    // Multiplied with the vertex colors when drawing
+    pub color: glm::Vec4,
+    pub material: Material,
+    // Texture sampled for the diffuse color, bound to texture unit 0 when drawing
+    pub diffuse_texture: Option<u32>,
+    // Tangent space normal map, bound to texture unit 1. Only used if the mesh has tangents
+    pub normal_texture: Option<u32>,
+
+    // A node that is not visible is not drawn, and neither are its children.
+    // Only reachable through setters, like the transform, since they change what the subtree draws
+    visible: bool,
+    layers: u32,
+
+    // Bounds of the node's own mesh in its own space, if it has one. Only reachable through setters,
+    // so that the world bounds are merged again when they change
+    mesh_aabb: Option<Aabb>,
+    mesh_bounding_sphere: Option<BoundingSphere>,
+    // Bounds of the node's mesh and all of its descendants in world space, updated along with the transformations.
+    // None if nothing in the subtree has a mesh, or if the subtree is unbounded
+    pub world_aabb: Option<Aabb>,
+    pub world_bounding_sphere: Option<BoundingSphere>,
+    // Set when something drawable in the subtree has no mesh bounds. It could be anywhere, so the subtree
+    // has no world bounds and can't be culled
+    world_unbounded: bool,
+    // How many visible meshes in the subtree have each combination of layers, updated along with the world bounds
+    world_drawables: Vec<(u32, u32)>,
 
-    pub children: Vec<*mut SceneNode>,
+    // Used to look nodes up by path and to group them, see SceneGraph::find and SceneGraph::tagged
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+
+    children: Vec<NodeId>,
+    parent: Option<NodeId>,
 }
 
 impl SceneNode {
-    pub fn new() -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+    pub fn new() -> SceneNode {
+        SceneNode {
             position: glm::zero(),
             rotation: glm::zero(),
+            rotation_order: EulerOrder::XYZ,
+            orientation: None,
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
+            dirty: true,
             current_transformation_matrix: glm::identity(),
             vao_id: 0,
             index_count: -1,
+            mesh_name: None,
+            color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            material: Material::default(),
+            diffuse_texture: None,
+            normal_texture: None,
+            visible: true,
+            layers: LAYER_DEFAULT,
+            mesh_aabb: None,
+            mesh_bounding_sphere: None,
+            world_aabb: None,
+            world_bounding_sphere: None,
+            world_unbounded: false,
+            world_drawables: vec![],
+            name: None,
+            tags: vec![],
             children: vec![],
-        })))
+            parent: None,
+        }
     }
-    pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
-        ManuallyDrop::new(Pin::new(Box::new(SceneNode {
-            position: glm::zero(),
-            rotation: glm::zero(),
-            scale: glm::vec3(1.0, 1.0, 1.0),
-            reference_point: glm::zero(),
-            current_transformation_matrix: glm::identity(),
+    pub fn from_vao(vao_id: u32, index_count: i32) -> SceneNode {
+        SceneNode {
             vao_id, index_count,
-            children: vec![],
-        })))
+            ..SceneNode::new()
+        }
+    }
+    // A drawable node for a mesh that has been uploaded to the given VAO
+    pub fn from_mesh(vao_id: u32, mesh: &Mesh) -> SceneNode {
+        SceneNode {
+            mesh_aabb: Some(mesh.aabb),
+            mesh_bounding_sphere: Some(mesh.bounding_sphere),
+            material: mesh.material.clone(),
+            ..SceneNode::from_vao(vao_id, mesh.index_count)
+        }
+    }
+    pub fn with_mesh_name(mut self, mesh_name: &str) -> SceneNode {
+        self.mesh_name = Some(mesh_name.to_string());
+        self
+    }
+    pub fn with_name(mut self, name: &str) -> SceneNode {
+        self.name = Some(name.to_string());
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_tag(mut self, tag: &str) -> SceneNode {
+        self.tags.push(tag.to_string());
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_layers(mut self, layers: u32) -> SceneNode {
+        self.layers = layers;
+        self
+    }
+    pub fn in_layers(&self, layers: u32) -> bool {
+        self.layers & layers != 0
+    }
+    // The number of meshes a pass with the given layers draws from this subtree, if none of it is culled.
+    // Like the world bounds, this is as of the last update
+    pub fn drawable_count(&self, layers: u32) -> u32 {
+        self.world_drawables.iter()
+            .filter(|&&(drawable_layers, _)| drawable_layers & layers != 0)
+            .map(|&(_, count)| count)
+            .sum()
+    }
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+    #[allow(dead_code)]
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn position(&self) -> glm::Vec3 {
+        self.position
+    }
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.position = position;
+        self.dirty = true;
+    }
+    pub fn rotation(&self) -> glm::Vec3 {
+        self.rotation
+    }
+    pub fn set_rotation(&mut self, rotation: glm::Vec3) {
+        self.rotation = rotation;
+        self.dirty = true;
+    }
+    pub fn rotation_order(&self) -> EulerOrder {
+        self.rotation_order
+    }
+    pub fn set_rotation_order(&mut self, rotation_order: EulerOrder) {
+        self.rotation_order = rotation_order;
+        self.dirty = true;
+    }
+    pub fn orientation(&self) -> Option<glm::Quat> {
+        self.orientation
+    }
+    // Pass None to go back to using the Euler angles in rotation
+    pub fn set_orientation(&mut self, orientation: Option<glm::Quat>) {
+        self.orientation = orientation;
+        self.dirty = true;
+    }
+    pub fn scale(&self) -> glm::Vec3 {
+        self.scale
+    }
+    pub fn set_scale(&mut self, scale: glm::Vec3) {
+        self.scale = scale;
+        self.dirty = true;
+    }
+    pub fn visible(&self) -> bool {
+        self.visible
     }
-    pub fn add_child(&mut self, child: &SceneNode) {
-        self.children.push(child as *const SceneNode as *mut SceneNode)
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        self.dirty = true;
     }
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+    pub fn set_layers(&mut self, layers: u32) {
+        self.layers = layers;
+        self.dirty = true;
+    }
+    pub fn index_count(&self) -> i32 {
+        self.index_count
+    }
+    pub fn set_index_count(&mut self, index_count: i32) {
+        self.index_count = index_count;
+        self.dirty = true;
+    }
+    pub fn mesh_aabb(&self) -> Option<Aabb> {
+        self.mesh_aabb
+    }
+    pub fn set_mesh_aabb(&mut self, mesh_aabb: Option<Aabb>) {
+        self.mesh_aabb = mesh_aabb;
+        self.dirty = true;
+    }
+    pub fn mesh_bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.mesh_bounding_sphere
+    }
+    pub fn set_mesh_bounding_sphere(&mut self, mesh_bounding_sphere: Option<BoundingSphere>) {
+        self.mesh_bounding_sphere = mesh_bounding_sphere;
+        self.dirty = true;
+    }
+    pub fn reference_point(&self) -> glm::Vec3 {
+        self.reference_point
+    }
+    pub fn set_reference_point(&mut self, reference_point: glm::Vec3) {
+        self.reference_point = reference_point;
+        self.dirty = true;
+    }
+
+    // Returns whether the node was dirty, and marks it as clean.
+    // Used when updating the transformations, after which current_transformation_matrix is up to date again
+    fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    pub fn rotation_matrix(&self) -> glm::Mat3 {
+        match &self.orientation {
+            Some(orientation) => glm::quat_to_mat3(&glm::quat_normalize(orientation)),
+            None => self.rotation_order.to_mat3(&self.rotation),
+        }
+    }
+
+    // The transformation from this node's space into its parent's space
+    pub fn local_transformation(&self) -> glm::Mat4 {
+        let origin = glm::mat4(
+            1.0, 0.0, 0.0, self.reference_point[0],
+            0.0, 1.0, 0.0, self.reference_point[1],
+            0.0, 0.0, 1.0, self.reference_point[2],
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let notorigin = glm::mat4(
+            1.0, 0.0, 0.0, -self.reference_point[0],
+            0.0, 1.0, 0.0, -self.reference_point[1],
+            0.0, 0.0, 1.0, -self.reference_point[2],
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let translation = glm::mat4(
+            1.0, 0.0, 0.0, self.position[0],
+            0.0, 1.0, 0.0, self.position[1],
+            0.0, 0.0, 1.0, self.position[2],
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        let scaling = glm::mat4(
+            self.scale[0], 0.0, 0.0, 0.0,
+            0.0, self.scale[1], 0.0, 0.0,
+            0.0, 0.0, self.scale[2], 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+
+        // Scaling and rotation both happen about the reference point
+        translation * origin * glm::mat3_to_mat4(&self.rotation_matrix()) * scaling * notorigin
+    }
+
+    // Sets position, rotation and scale so that local_transformation() becomes the given matrix.
+    // The matrix is expected to be a scaling, a rotation and a translation, as produced by local_transformation().
+    // Shear, which appears when a rotated node sits below a non-uniformly scaled parent, can not be represented and is lost
+    pub fn set_local_transformation(&mut self, matrix: &glm::Mat4) {
+        let scale_rotation = glm::mat4_to_mat3(matrix);
+        let mut scale = glm::vec3(
+            scale_rotation.column(0).norm(),
+            scale_rotation.column(1).norm(),
+            scale_rotation.column(2).norm(),
+        );
+        // A mirroring shows up as a negative determinant, and is put in the x scale
+        if glm::determinant(&scale_rotation) < 0.0 {
+            scale.x = -scale.x;
+        }
+        self.scale = scale;
+        self.dirty = true;
+
+        // The columns divided by the scale are the rotated axes. An axis with zero scale has been flattened away,
+        // so it is rebuilt from the other two. With only one axis left the others can point anywhere around it,
+        // and with none left the old rotation is kept
+        let axes: Vec<Option<glm::Vec3>> = (0..3)
+            .map(|i| if scale[i].abs() > 1e-6 { Some(scale_rotation.column(i).into_owned() / scale[i]) } else { None })
+            .collect();
+        let (x, y, z) = match (axes[0], axes[1], axes[2]) {
+            (Some(x), Some(y), Some(z)) => (x, y, z),
+            (None, Some(y), Some(z)) => (glm::cross(&y, &z), y, z),
+            (Some(x), None, Some(z)) => (x, glm::cross(&z, &x), z),
+            (Some(x), Some(y), None) => (x, y, glm::cross(&x, &y)),
+            (Some(x), None, None) => { let y = perpendicular(&x); (x, y, glm::cross(&x, &y)) }
+            (None, Some(y), None) => { let z = perpendicular(&y); (glm::cross(&y, &z), y, z) }
+            (None, None, Some(z)) => { let x = perpendicular(&z); (x, glm::cross(&z, &x), z) }
+            _ => {
+                let old = self.rotation_matrix();
+                (old.column(0).into_owned(), old.column(1).into_owned(), old.column(2).into_owned())
+            }
+        };
+        let rotation = glm::mat3(
+            x.x, y.x, z.x,
+            x.y, y.y, z.y,
+            x.z, y.z, z.z,
+        );
+        match self.orientation {
+            Some(_) => self.orientation = Some(glm::mat3_to_quat(&rotation)),
+            None => self.rotation = self.rotation_order.angles_from_mat3(&rotation),
+        }
+
+        // The translation column is position + reference_point - R * S * reference_point
+        let translation = glm::vec3(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)]);
+        self.position = translation - self.reference_point + scale_rotation * self.reference_point;
+    }
+    // World space queries. These are derived from current_transformation_matrix, so they reflect the
+    // transformations as of the last update. Forward is -z, up is +y and right is +x in the node's own space
+
+    #[allow(dead_code)]
+    pub fn world_position(&self) -> glm::Vec3 {
+        self.transform_point(&glm::zero())
+    }
+    #[allow(dead_code)]
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::normalize(&self.transform_direction(&glm::vec3(0.0, 0.0, -1.0)))
+    }
+    #[allow(dead_code)]
+    pub fn up(&self) -> glm::Vec3 {
+        glm::normalize(&self.transform_direction(&glm::vec3(0.0, 1.0, 0.0)))
+    }
+    #[allow(dead_code)]
+    pub fn right(&self) -> glm::Vec3 {
+        glm::normalize(&self.transform_direction(&glm::vec3(1.0, 0.0, 0.0)))
+    }
+
+    // The transformation from world space into the node's space
+    #[allow(dead_code)]
+    pub fn inverse_world_transformation(&self) -> glm::Mat4 {
+        glm::inverse(&self.current_transformation_matrix)
+    }
+
+    // Local to world. Directions are not affected by translation, and are not normalized
+    pub fn transform_point(&self, point: &glm::Vec3) -> glm::Vec3 {
+        (self.current_transformation_matrix * point.push(1.0)).xyz()
+    }
+    #[allow(dead_code)]
+    pub fn transform_direction(&self, direction: &glm::Vec3) -> glm::Vec3 {
+        (self.current_transformation_matrix * direction.push(0.0)).xyz()
+    }
+
+    // World to local
+    #[allow(dead_code)]
+    pub fn inverse_transform_point(&self, point: &glm::Vec3) -> glm::Vec3 {
+        (self.inverse_world_transformation() * point.push(1.0)).xyz()
+    }
+    #[allow(dead_code)]
+    pub fn inverse_transform_direction(&self, direction: &glm::Vec3) -> glm::Vec3 {
+        (self.inverse_world_transformation() * direction.push(0.0)).xyz()
+    }
+
     pub fn print(&self) {
         let m = self.current_transformation_matrix;
         let matrix_string = format!(
@@ -69,6 +484,7 @@ impl SceneNode {
         );
         println!(
 "SceneNode {{
+    Name:      {}
     VAO:       {}
     Indices:   {}
     Children:  {}
@@ -77,6 +493,7 @@ impl SceneNode {
     Reference: [{:.2}, {:.2}, {:.2}]
     Current Transformation Matrix: {}
 }}",
+            self.name.as_deref().unwrap_or("<unnamed>"),
             self.vao_id,
             self.index_count,
             self.children.len(),
@@ -93,3 +510,551 @@ impl SceneNode {
         );
     }
 }
+
+struct Slot {
+    generation: u32,
+    node: Option<SceneNode>,
+}
+
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    root: NodeId,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        let mut graph = SceneGraph {
+            slots: vec![],
+            free: vec![],
+            root: NodeId { index: 0, generation: 0 },
+        };
+        graph.root = graph.add_node(SceneNode::new());
+        graph
+    }
+
+    // The root node is created along with the graph, and is the parent of everything that is drawn
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    // Moves a node into the graph. The node is not connected to anything until it is given a parent with add_child
+    pub fn add_node(&mut self, node: SceneNode) -> NodeId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId { index, generation: slot.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, node: Some(node) });
+                NodeId { index: self.slots.len() - 1, generation: 0 }
+            }
+        }
+    }
+
+    pub fn add_child(&mut self, parent: NodeId, child: NodeId) {
+        assert!(parent != child, "A node can not be its own child");
+        assert!(self[child].parent.is_none(), "Node already has a parent");
+        assert!(!self.is_ancestor(child, parent), "A node can not be the child of its own descendant");
+        self[child].parent = Some(parent);
+        self[child].dirty = true;
+        self[parent].children.push(child);
+    }
+
+    // Removes a node and everything below it from the graph. Their NodeIds become invalid
+    pub fn remove(&mut self, id: NodeId) {
+        assert!(id != self.root, "The root node can not be removed");
+        self.detach(id);
+        for removed in self.descendants(id) {
+            let slot = &mut self.slots[removed.index];
+            slot.node = None;
+            slot.generation += 1;
+            self.free.push(removed.index);
+        }
+    }
+
+    // Disconnects a node from its parent. The node and its children stay in the graph, but are not
+    // part of the scene until they are given a new parent
+    pub fn detach(&mut self, id: NodeId) {
+        if let Some(parent) = self[id].parent.take() {
+            self[parent].children.retain(|&child| child != id);
+            // The parent's bounds no longer include this node
+            self[parent].dirty = true;
+        }
+    }
+
+    // Moves a node, along with its children, to a new parent.
+    // With keep_world_transform the node's position and rotation are changed so that it stays where it is
+    // in the world, otherwise it keeps its local transform and moves along with its new parent
+    #[allow(dead_code)]
+    pub fn reparent(&mut self, id: NodeId, new_parent: NodeId, keep_world_transform: bool) {
+        assert!(!self.is_ancestor(id, new_parent), "A node can not be moved below itself");
+        let world_transformation = self.world_transformation(id);
+        self.detach(id);
+        self.add_child(new_parent, id);
+        if keep_world_transform {
+            let local = glm::inverse(&self.world_transformation(new_parent)) * world_transformation;
+            self[id].set_local_transformation(&local);
+        }
+    }
+
+    // True if descendant is ancestor itself, or somewhere below it
+    pub fn is_ancestor(&self, ancestor: NodeId, descendant: NodeId) -> bool {
+        let mut current = Some(descendant);
+        while let Some(id) = current {
+            if id == ancestor { return true }
+            current = self[id].parent;
+        }
+        false
+    }
+
+    // Computes the transformation from the node's space to world space by walking up to the root.
+    // Unlike current_transformation_matrix this does not depend on the transformations having been updated
+    #[allow(dead_code)]
+    pub fn world_transformation(&self, id: NodeId) -> glm::Mat4 {
+        let node = &self[id];
+        match node.parent {
+            Some(parent) => self.world_transformation(parent) * node.local_transformation(),
+            None => node.local_transformation(),
+        }
+    }
+
+    // Brings current_transformation_matrix and the world bounds of every node below the root up to date.
+    // Only nodes that are dirty, or that have a dirty ancestor, get their transformation matrix recomputed,
+    // and only the bounds of those nodes and their ancestors are merged again
+    pub fn update_transformations(&mut self) {
+        self.update_subtree(self.root, &glm::identity(), false);
+    }
+
+    // Returns whether anything in the subtree changed, in which case the world bounds are merged again on the way up
+    fn update_subtree(&mut self, id: NodeId, transformation_so_far: &glm::Mat4, parent_changed: bool) -> bool {
+        let node = &mut self[id];
+
+        // Update the node's transformation matrix
+        let changed = node.take_dirty() || parent_changed;
+        if changed {
+            node.current_transformation_matrix = transformation_so_far * node.local_transformation();
+        }
+
+        // Recurse
+        let transformation = node.current_transformation_matrix;
+        let mut subtree_changed = changed;
+        for i in 0..self[id].children.len() {
+            let child = self[id].children[i];
+            subtree_changed |= self.update_subtree(child, &transformation, changed);
+        }
+
+        if subtree_changed {
+            self.update_world_bounds(id);
+        }
+        subtree_changed
+    }
+
+    // Merges the node's own bounds with the world bounds of its children, and counts the meshes in the subtree.
+    // Expects current_transformation_matrix and the bounds of the children to be up to date
+    fn update_world_bounds(&mut self, id: NodeId) {
+        let node = &self[id];
+        let transformation = node.current_transformation_matrix;
+        let mut aabb = node.mesh_aabb.map(|aabb| aabb.transform(&transformation));
+        let mut sphere = node.mesh_bounding_sphere.map(|sphere| sphere.transform(&transformation));
+        let mut unbounded = node.index_count > 0 && (node.mesh_aabb.is_none() || node.mesh_bounding_sphere.is_none());
+
+        let mut drawables: Vec<(u32, u32)> = vec![];
+        let mut count_drawables = |layers: u32, count: u32| match drawables.iter_mut().find(|(l, _)| *l == layers) {
+            Some((_, total)) => *total += count,
+            None => drawables.push((layers, count)),
+        };
+        if node.visible && node.index_count > 0 {
+            count_drawables(node.layers, 1);
+        }
+
+        for &child in &node.children {
+            let child = &self[child];
+            if node.visible {
+                for &(layers, count) in &child.world_drawables {
+                    count_drawables(layers, count);
+                }
+            }
+            unbounded |= child.world_unbounded;
+            aabb = match (aabb, child.world_aabb) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                (a, b) => a.or(b),
+            };
+            sphere = match (sphere, child.world_bounding_sphere) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                (a, b) => a.or(b),
+            };
+        }
+
+        let node = &mut self[id];
+        node.world_unbounded = unbounded;
+        node.world_aabb = if unbounded { None } else { aabb };
+        node.world_bounding_sphere = if unbounded { None } else { sphere };
+        node.world_drawables = drawables;
+    }
+
+    // Looks up a node by the names along the path from the root, e.g. "terrain/helicopter_3/main_rotor".
+    // The root itself is not part of the path
+    #[allow(dead_code)]
+    pub fn find(&self, path: &str) -> Option<NodeId> {
+        self.find_from(self.root, path)
+    }
+
+    // Like find, but with the path relative to the given node
+    pub fn find_from(&self, start: NodeId, path: &str) -> Option<NodeId> {
+        let mut current = start;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            current = *self[current].children.iter()
+                .find(|&&child| self[child].name.as_deref() == Some(name))?;
+        }
+        Some(current)
+    }
+
+    // All nodes in the graph that have the given tag, attached to the scene or not
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = NodeId> + 'a {
+        self.slots.iter().enumerate().filter_map(move |(index, slot)| match &slot.node {
+            Some(node) if node.has_tag(tag) => Some(NodeId { index, generation: slot.generation }),
+            _ => None,
+        })
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&SceneNode> {
+        self.slots.get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        self.slots.get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    // Depth first, parents before children, in the order the children were added
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut order = vec![];
+        let mut stack = vec![id];
+        while let Some(current) = stack.pop() {
+            order.push(current);
+            stack.extend(self[current].children.iter().rev());
+        }
+        order
+    }
+}
+
+// Square brackets can be used to reach a node directly, e.g. graph[rotor].set_rotation(glm::vec3(0.0, 1.0, 0.0))
+impl Index<NodeId> for SceneGraph {
+    type Output = SceneNode;
+    fn index(&self, id: NodeId) -> &SceneNode {
+        self.get(id).expect("Invalid NodeId, the node has been removed")
+    }
+}
+
+impl IndexMut<NodeId> for SceneGraph {
+    fn index_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        self.get_mut(id).expect("Invalid NodeId, the node has been removed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &glm::Mat4, b: &glm::Mat4) {
+        assert!((a - b).amax() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn node(position: glm::Vec3, rotation: glm::Vec3, scale: glm::Vec3) -> SceneNode {
+        let mut node = SceneNode::new();
+        node.set_position(position);
+        node.set_rotation(rotation);
+        node.set_scale(scale);
+        node
+    }
+
+    const ORDERS: [EulerOrder; 6] = [EulerOrder::XYZ, EulerOrder::XZY, EulerOrder::YXZ, EulerOrder::YZX, EulerOrder::ZXY, EulerOrder::ZYX];
+
+    fn assert_close3(a: &glm::Mat3, b: &glm::Mat3) {
+        assert!((a - b).amax() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn euler_angles_round_trip() {
+        let half_pi = std::f32::consts::FRAC_PI_2;
+        for &order in &ORDERS {
+            let [_, middle, _] = order.axes();
+            let mut gimbal_lock = glm::vec3(0.4, -0.7, 1.1);
+            gimbal_lock[middle] = half_pi;
+            let mut negative_gimbal_lock = glm::vec3(-0.2, 0.9, 0.3);
+            negative_gimbal_lock[middle] = -half_pi;
+
+            for angles in &[glm::vec3(0.3, -0.5, 1.2), glm::vec3(-2.5, 1.0, 3.0), glm::zero(), gimbal_lock, negative_gimbal_lock] {
+                let rotation = order.to_mat3(angles);
+                let recovered = order.angles_from_mat3(&rotation);
+                assert_close3(&order.to_mat3(&recovered), &rotation);
+            }
+        }
+    }
+
+    #[test]
+    fn euler_quaternion_matches_matrix() {
+        for &order in &ORDERS {
+            let angles = glm::vec3(0.3, -0.5, 1.2);
+            assert_close3(&glm::quat_to_mat3(&order.to_quat(&angles)), &order.to_mat3(&angles));
+        }
+    }
+
+    #[test]
+    fn reparent_keeps_world_transform() {
+        let mut graph = SceneGraph::new();
+        // Both parents are rotated and uniformly scaled, so the child's non-uniform scale stays representable
+        let old_parent = graph.add_node(node(glm::vec3(1.0, 2.0, 3.0), glm::vec3(0.0, 0.7, 0.0), glm::vec3(2.0, 2.0, 2.0)));
+        let new_parent = graph.add_node(node(glm::vec3(-4.0, 0.0, 1.0), glm::vec3(0.5, 0.0, 0.2), glm::vec3(0.5, 0.5, 0.5)));
+        let mut child = node(glm::vec3(1.0, -1.0, 2.0), glm::vec3(0.3, 0.2, 0.1), glm::vec3(1.0, 2.0, 3.0));
+        child.set_reference_point(glm::vec3(1.0, 0.0, -1.0));
+        let child = graph.add_node(child);
+        let root = graph.root();
+        graph.add_child(root, old_parent);
+        graph.add_child(root, new_parent);
+        graph.add_child(old_parent, child);
+
+        let before = graph.world_transformation(child);
+        graph.reparent(child, new_parent, true);
+        assert_eq!(graph[child].parent(), Some(new_parent));
+        assert!(graph[old_parent].children().is_empty());
+        assert_close(&graph.world_transformation(child), &before);
+        assert_eq!(graph[child].reference_point(), glm::vec3(1.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn reparent_without_keeping_world_transform_keeps_local_transform() {
+        let mut graph = SceneGraph::new();
+        let old_parent = graph.add_node(node(glm::vec3(1.0, 2.0, 3.0), glm::zero(), glm::vec3(1.0, 1.0, 1.0)));
+        let new_parent = graph.add_node(SceneNode::new());
+        let child = graph.add_node(node(glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.1, 0.2, 0.3), glm::vec3(1.0, 2.0, 1.0)));
+        graph.add_child(old_parent, child);
+
+        let local = graph[child].local_transformation();
+        graph.reparent(child, new_parent, false);
+        assert_close(&graph[child].local_transformation(), &local);
+    }
+
+    #[test]
+    #[should_panic(expected = "below itself")]
+    fn reparent_below_descendant_panics() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add_node(SceneNode::new());
+        let child = graph.add_node(SceneNode::new());
+        let grandchild = graph.add_node(SceneNode::new());
+        graph.add_child(parent, child);
+        graph.add_child(child, grandchild);
+        graph.reparent(parent, grandchild, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "own descendant")]
+    fn add_child_below_descendant_panics() {
+        let mut graph = SceneGraph::new();
+        let detached = graph.add_node(SceneNode::new());
+        let child = graph.add_node(SceneNode::new());
+        let grandchild = graph.add_node(SceneNode::new());
+        graph.add_child(detached, child);
+        graph.add_child(child, grandchild);
+        graph.add_child(grandchild, detached);
+    }
+
+    #[test]
+    fn drawable_count_follows_visibility_and_layers() {
+        let mut graph = SceneGraph::new();
+        let parent = graph.add_node(SceneNode::from_vao(1, 3));
+        let debug = graph.add_node(SceneNode::from_vao(1, 3).with_layers(LAYER_DEBUG));
+        let hidden = graph.add_node(SceneNode::from_vao(1, 3));
+        let empty = graph.add_node(SceneNode::new());
+        graph[hidden].set_visible(false);
+        graph.add_child(parent, debug);
+        graph.add_child(parent, hidden);
+        graph.add_child(parent, empty);
+        for &id in &[debug, hidden, empty, parent] {
+            graph.update_world_bounds(id);
+        }
+
+        assert_eq!(graph[parent].drawable_count(LAYER_DEFAULT), 1);
+        assert_eq!(graph[parent].drawable_count(LAYER_DEBUG), 1);
+        assert_eq!(graph[parent].drawable_count(LAYER_ALL), 2);
+
+        graph[parent].set_visible(false);
+        graph.update_world_bounds(parent);
+        assert_eq!(graph[parent].drawable_count(LAYER_ALL), 0);
+    }
+
+    // A drawable node with bounds from -1 to 1 along each axis
+    fn boxed() -> SceneNode {
+        let mut node = SceneNode::from_vao(1, 3);
+        node.set_mesh_aabb(Some(Aabb { min: glm::vec3(-1.0, -1.0, -1.0), max: glm::vec3(1.0, 1.0, 1.0) }));
+        node.set_mesh_bounding_sphere(Some(BoundingSphere { center: glm::zero(), radius: 3f32.sqrt() }));
+        node
+    }
+
+    #[test]
+    fn update_transformations_skips_clean_nodes() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let moved = graph.add_node(SceneNode::new());
+        let sibling = graph.add_node(SceneNode::new());
+        graph.add_child(root, moved);
+        graph.add_child(root, sibling);
+        graph.update_transformations();
+
+        // A matrix that would be overwritten if the sibling were recomputed
+        let marker = glm::scaling(&glm::vec3(7.0, 7.0, 7.0));
+        graph[sibling].current_transformation_matrix = marker;
+        graph[moved].set_position(glm::vec3(1.0, 2.0, 3.0));
+        graph.update_transformations();
+        assert_close(&graph[moved].current_transformation_matrix, &graph.world_transformation(moved));
+        assert_close(&graph[sibling].current_transformation_matrix, &marker);
+    }
+
+    #[test]
+    fn update_transformations_recomputes_below_dirty_parents() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let parent = graph.add_node(SceneNode::new());
+        let child = graph.add_node(node(glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.5, 0.0), glm::vec3(1.0, 1.0, 1.0)));
+        let grandchild = graph.add_node(boxed());
+        graph.add_child(root, parent);
+        graph.add_child(parent, child);
+        graph.add_child(child, grandchild);
+        graph.update_transformations();
+
+        // Only the parent is dirty, but everything below it moves along
+        graph[parent].set_position(glm::vec3(10.0, 0.0, 0.0));
+        graph.update_transformations();
+        for &id in &[parent, child, grandchild] {
+            assert_close(&graph[id].current_transformation_matrix, &graph.world_transformation(id));
+        }
+        let bounds = graph[root].world_aabb.expect("No bounds");
+        assert!(bounds.min.x > 8.0 && bounds.max.x < 12.0, "{:?}", bounds);
+    }
+
+    #[test]
+    fn detach_and_remove_refresh_the_old_parents_bounds() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let parent = graph.add_node(boxed());
+        let detached = graph.add_node(boxed());
+        let removed = graph.add_node(boxed());
+        graph[detached].set_position(glm::vec3(10.0, 0.0, 0.0));
+        graph[removed].set_position(glm::vec3(0.0, 10.0, 0.0));
+        graph.add_child(root, parent);
+        graph.add_child(parent, detached);
+        graph.add_child(parent, removed);
+        graph.update_transformations();
+        assert_eq!(graph[root].world_aabb.unwrap().max, glm::vec3(11.0, 11.0, 1.0));
+        assert_eq!(graph[root].drawable_count(LAYER_ALL), 3);
+
+        graph.detach(detached);
+        graph.update_transformations();
+        assert_eq!(graph[root].world_aabb.unwrap().max, glm::vec3(1.0, 11.0, 1.0));
+        assert_eq!(graph[root].drawable_count(LAYER_ALL), 2);
+
+        graph.remove(removed);
+        graph.update_transformations();
+        assert_eq!(graph[root].world_aabb.unwrap().max, glm::vec3(1.0, 1.0, 1.0));
+        assert_eq!(graph[root].drawable_count(LAYER_ALL), 1);
+    }
+
+    #[test]
+    fn drawables_without_bounds_make_the_subtree_unbounded() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let parent = graph.add_node(boxed());
+        let empty = graph.add_node(SceneNode::new());
+        let unbounded = graph.add_node(SceneNode::from_vao(1, 3));
+        graph.add_child(root, parent);
+        graph.add_child(parent, empty);
+        graph.update_transformations();
+        // Nodes that draw nothing don't need bounds
+        assert!(graph[root].world_aabb.is_some() && graph[root].world_bounding_sphere.is_some());
+
+        graph.add_child(parent, unbounded);
+        graph.update_transformations();
+        for &id in &[root, parent] {
+            assert!(graph[id].world_aabb.is_none() && graph[id].world_bounding_sphere.is_none());
+        }
+
+        graph.remove(unbounded);
+        graph.update_transformations();
+        assert!(graph[root].world_aabb.is_some() && graph[root].world_bounding_sphere.is_some());
+    }
+
+    #[test]
+    fn world_space_queries() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let parent = graph.add_node(node(glm::vec3(5.0, 0.0, -2.0), glm::zero(), glm::vec3(1.0, 1.0, 1.0)));
+        let child = graph.add_node(node(glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, std::f32::consts::FRAC_PI_2, 0.0), glm::vec3(2.0, 2.0, 2.0)));
+        graph.add_child(root, parent);
+        graph.add_child(parent, child);
+        graph.update_transformations();
+
+        let rotated = &graph[child];
+        let assert_close3 = |a: glm::Vec3, b: glm::Vec3| assert!(glm::distance(&a, &b) < 1e-5, "{} != {}", a, b);
+        assert_close3(rotated.world_position(), glm::vec3(5.0, 1.0, -2.0));
+        // A quarter turn about y turns -z into -x, and x into -z
+        assert_close3(rotated.forward(), glm::vec3(-1.0, 0.0, 0.0));
+        assert_close3(rotated.right(), glm::vec3(0.0, 0.0, -1.0));
+        assert_close3(rotated.up(), glm::vec3(0.0, 1.0, 0.0));
+        // Directions are scaled, but not translated
+        assert_close3(rotated.transform_direction(&glm::vec3(0.0, 0.0, -1.0)), glm::vec3(-2.0, 0.0, 0.0));
+
+        let p = glm::vec3(0.3, -1.2, 4.0);
+        assert_close3(rotated.inverse_transform_point(&rotated.transform_point(&p)), p);
+        assert_close3(rotated.inverse_transform_direction(&rotated.transform_direction(&p)), p);
+    }
+
+    #[test]
+    fn remove_invalidates_ids() {
+        let mut graph = SceneGraph::new();
+        let root = graph.root();
+        let child = graph.add_node(SceneNode::new().with_name("child"));
+        let grandchild = graph.add_node(SceneNode::new());
+        graph.add_child(root, child);
+        graph.add_child(child, grandchild);
+
+        graph.remove(child);
+        assert!(graph.get(child).is_none());
+        assert!(graph.get(grandchild).is_none());
+        assert!(graph[root].children().is_empty());
+
+        // The freed slots are reused, but the old ids must not reach the new nodes
+        let reused = graph.add_node(SceneNode::new());
+        let reused_too = graph.add_node(SceneNode::new());
+        assert!(graph.get(reused).is_some() && graph.get(reused_too).is_some());
+        assert!(graph.get(child).is_none());
+        assert!(graph.get(grandchild).is_none());
+    }
+
+    #[test]
+    fn set_local_transformation_round_trips() {
+        let mut source = node(glm::vec3(1.0, -2.0, 3.0), glm::vec3(0.4, -0.3, 1.2), glm::vec3(-1.0, 2.0, 0.5));
+        source.set_reference_point(glm::vec3(0.5, 1.0, 0.0));
+        let matrix = source.local_transformation();
+
+        let mut target = SceneNode::new();
+        target.set_reference_point(glm::vec3(0.5, 1.0, 0.0));
+        target.set_local_transformation(&matrix);
+        assert_close(&target.local_transformation(), &matrix);
+    }
+
+    #[test]
+    fn set_local_transformation_with_zero_scale() {
+        for &scale in &[glm::vec3(0.0, 2.0, 1.0), glm::vec3(1.0, 0.0, 3.0), glm::vec3(2.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, 0.0, 0.0)] {
+            let matrix = node(glm::vec3(1.0, 2.0, 3.0), glm::vec3(0.3, 0.5, -0.2), scale).local_transformation();
+            let mut target = SceneNode::new();
+            target.set_local_transformation(&matrix);
+            assert!(target.rotation().iter().all(|angle| angle.is_finite()), "NaN rotation for scale {}", scale);
+            assert_close(&target.local_transformation(), &matrix);
+        }
+    }
+}
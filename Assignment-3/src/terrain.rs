@@ -0,0 +1,191 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+
+// The ground at a point on the terrain
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainSample {
+    pub height: f32,
+    pub normal: glm::Vec3,
+}
+
+// Looks up the height and normal of a terrain mesh at any x and z, in the mesh's own space, so objects below the
+// terrain node in the scene graph can follow the ground. Triangles are sorted into a grid over x and z, so a lookup
+// only has to test the few triangles in one cell no matter how large the terrain is
+pub struct TerrainQuery {
+    positions: Vec<glm::Vec3>,
+    // Empty if the mesh has no normals, in which case the normals of the triangles are used
+    normals: Vec<glm::Vec3>,
+    triangles: Vec<[u32; 3]>,
+    min: glm::Vec2,
+    cell_size: glm::Vec2,
+    columns: usize,
+    rows: usize,
+    // Indices of the triangles overlapping every cell, row by row
+    cells: Vec<Vec<u32>>,
+}
+
+impl TerrainQuery {
+    pub fn new(mesh: &Mesh) -> TerrainQuery {
+        let positions: Vec<glm::Vec3> = mesh.vertices.chunks_exact(3).map(glm::make_vec3).collect();
+        let normals: Vec<glm::Vec3> = if mesh.normals.len() == mesh.vertices.len() {
+            mesh.normals.chunks_exact(3).map(glm::make_vec3).collect()
+        } else {
+            vec![]
+        };
+        let triangles: Vec<[u32; 3]> = mesh.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+        // About one triangle per cell, for a terrain that is about as wide as it is deep
+        let side = ((triangles.len() as f32).sqrt().ceil() as usize).clamp(1, 1024);
+        let (columns, rows) = (side, side);
+        let min = glm::vec2(mesh.aabb.min.x, mesh.aabb.min.z);
+        let size = glm::vec2(mesh.aabb.max.x, mesh.aabb.max.z) - min;
+        let cell_size = glm::vec2((size.x / columns as f32).max(1e-6), (size.y / rows as f32).max(1e-6));
+
+        let mut query = TerrainQuery { positions, normals, triangles, min, cell_size, columns, rows, cells: vec![vec![]; columns * rows] };
+        for (index, triangle) in query.triangles.iter().enumerate() {
+            let xz = |vertex: u32| query.positions[vertex as usize].xz();
+            let (a, b, c) = (xz(triangle[0]), xz(triangle[1]), xz(triangle[2]));
+            let (first_column, first_row) = query.cell(glm::min2(&a, &glm::min2(&b, &c)));
+            let (last_column, last_row) = query.cell(glm::max2(&a, &glm::max2(&b, &c)));
+            for row in first_row..=last_row {
+                for column in first_column..=last_column {
+                    query.cells[row * columns + column].push(index as u32);
+                }
+            }
+        }
+        query
+    }
+
+    // The cell a point is in, clamped to the grid
+    fn cell(&self, point: glm::Vec2) -> (usize, usize) {
+        let relative = (point - self.min).component_div(&self.cell_size);
+        let clamp = |value: f32, count: usize| (value.max(0.0) as usize).min(count - 1);
+        (clamp(relative.x, self.columns), clamp(relative.y, self.rows))
+    }
+
+    // The height and normal interpolated across the triangle below or above the point, or None if the point is
+    // outside the terrain. Where triangles overlap, like under an overhang, the highest one wins
+    pub fn sample(&self, x: f32, z: f32) -> Option<TerrainSample> {
+        let point = glm::vec2(x, z);
+        let (column, row) = self.cell(point);
+        let mut best: Option<TerrainSample> = None;
+        for &index in &self.cells[row * self.columns + column] {
+            let triangle = self.triangles[index as usize];
+            let (a, b, c) = (
+                self.positions[triangle[0] as usize],
+                self.positions[triangle[1] as usize],
+                self.positions[triangle[2] as usize],
+            );
+
+            // Barycentric coordinates in the xz plane. Vertical triangles have no area there, and can't be stood on
+            let determinant = (b.z - c.z) * (a.x - c.x) + (c.x - b.x) * (a.z - c.z);
+            if determinant.abs() < 1e-12 {
+                continue;
+            }
+            let weight_a = ((b.z - c.z) * (point.x - c.x) + (c.x - b.x) * (point.y - c.z)) / determinant;
+            let weight_b = ((c.z - a.z) * (point.x - c.x) + (a.x - c.x) * (point.y - c.z)) / determinant;
+            let weight_c = 1.0 - weight_a - weight_b;
+            // A little slack so points on shared edges don't fall through the cracks
+            let epsilon = -1e-5;
+            if weight_a < epsilon || weight_b < epsilon || weight_c < epsilon {
+                continue;
+            }
+
+            let height = a.y * weight_a + b.y * weight_b + c.y * weight_c;
+            if best.is_some_and(|best| best.height >= height) {
+                continue;
+            }
+            let normal = if self.normals.is_empty() {
+                let normal = glm::cross(&(b - a), &(c - a));
+                if normal.y < 0.0 { -normal } else { normal }
+            } else {
+                let n = |vertex: u32| self.normals[vertex as usize];
+                n(triangle[0]) * weight_a + n(triangle[1]) * weight_b + n(triangle[2]) * weight_c
+            };
+            let normal = if normal.norm() > 0.0 { glm::normalize(&normal) } else { glm::vec3(0.0, 1.0, 0.0) };
+            best = Some(TerrainSample { height, normal });
+        }
+        best
+    }
+
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.sample(x, z).map(|sample| sample.height)
+    }
+
+    #[allow(dead_code)]
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<glm::Vec3> {
+        self.sample(x, z).map(|sample| sample.normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{Terrain, TerrainScale};
+
+    const SCALE: TerrainScale = TerrainScale { horizontal: 2.0, vertical: 1.0 };
+
+    // 4 by 3 heights, so the terrain goes from -3 to 3 along x and from -2 to 2 along z
+    fn bumpy() -> Mesh {
+        Terrain::from_heights(4, 3, &[
+            0.0, 1.0, 3.0, 2.0,
+            5.0, 0.5, 1.5, 4.0,
+            2.0, 2.5, 0.0, 1.0,
+        ], &SCALE)
+    }
+
+    #[test]
+    fn heights_at_vertices_are_exact() {
+        let terrain = bumpy();
+        let query = TerrainQuery::new(&terrain);
+        for vertex in terrain.vertices.chunks_exact(3) {
+            let height = query.height_at(vertex[0], vertex[2]).expect("Vertex is outside the terrain");
+            assert!((height - vertex[1]).abs() < 1e-5, "{} != {} at [{}, {}]", height, vertex[1], vertex[0], vertex[2]);
+        }
+    }
+
+    #[test]
+    fn heights_are_interpolated_across_triangles() {
+        let terrain = bumpy();
+        let query = TerrainQuery::new(&terrain);
+        for triangle in terrain.indices.chunks_exact(3) {
+            let corners: Vec<glm::Vec3> = triangle.iter()
+                .map(|&vertex| glm::make_vec3(&terrain.vertices[vertex as usize * 3..vertex as usize * 3 + 3]))
+                .collect();
+            let centroid = (corners[0] + corners[1] + corners[2]) / 3.0;
+            let height = query.height_at(centroid.x, centroid.z).unwrap();
+            assert!((height - centroid.y).abs() < 1e-5, "{} != {}", height, centroid.y);
+        }
+    }
+
+    #[test]
+    fn nothing_outside_the_terrain() {
+        let query = TerrainQuery::new(&bumpy());
+        assert!(query.sample(3.5, 0.0).is_none());
+        assert!(query.sample(0.0, -2.5).is_none());
+        assert!(query.sample(-100.0, 100.0).is_none());
+        assert!(query.sample(-3.0, 2.0).is_some());
+    }
+
+    #[test]
+    fn normal_on_a_slope() {
+        // Rising by 1 per column, 2 units apart, and falling by 0.5 per row
+        let heights: Vec<f32> = (0..3).flat_map(|row| (0..4).map(move |column| column as f32 - 0.5 * row as f32)).collect();
+        let expected = glm::normalize(&glm::vec3(-0.5, 1.0, 0.25));
+
+        let terrain = Terrain::from_heights(4, 3, &heights, &SCALE);
+        let query = TerrainQuery::new(&terrain);
+        for &(x, z) in &[(0.3, 0.7), (-2.5, -1.5), (2.9, 1.9)] {
+            let normal = query.normal_at(x, z).unwrap();
+            assert!(glm::distance(&normal, &expected) < 1e-5, "{} at [{}, {}]", normal, x, z);
+        }
+
+        // Without vertex normals the triangles' own normals are used, which point up no matter the winding
+        let mut flipped = terrain.indices.clone();
+        flipped.chunks_exact_mut(3).for_each(|triangle| triangle.swap(1, 2));
+        let without_normals = Mesh::new(terrain.vertices.clone(), vec![], vec![], flipped, [1.0, 1.0, 1.0, 1.0]);
+        let normal = TerrainQuery::new(&without_normals).normal_at(0.3, 0.7).unwrap();
+        assert!(glm::distance(&normal, &expected) < 1e-5, "{}", normal);
+    }
+}
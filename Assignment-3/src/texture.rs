@@ -0,0 +1,117 @@
+use std::os::raw::c_void;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl From<Wrap> for gl::types::GLenum {
+    fn from(wrap: Wrap) -> gl::types::GLenum {
+        match wrap {
+            Wrap::Repeat            => { gl::REPEAT          },
+            Wrap::MirroredRepeat    => { gl::MIRRORED_REPEAT },
+            Wrap::ClampToEdge       => { gl::CLAMP_TO_EDGE   },
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextureOptions {
+    pub wrap: Wrap,
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    // When enabled the min filter also blends between mipmap levels
+    pub mipmaps: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> TextureOptions {
+        TextureOptions {
+            wrap: Wrap::Repeat,
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmaps: true,
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct Texture {
+    pub texture_id: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Texture {
+    pub unsafe fn load(path: &str, options: &TextureOptions) -> Result<Texture, image::ImageError> {
+        let image = image::open(path)?;
+        Ok(Texture::from_image(image, options))
+    }
+
+    pub unsafe fn from_image(image: image::DynamicImage, options: &TextureOptions) -> Texture {
+        // Images are stored top row first, but OpenGL expects the bottom row first
+        let image = image.flipv().into_rgba8();
+        let (width, height) = image.dimensions();
+
+        let mut texture_id: u32 = 0;
+        gl::GenTextures(1, &mut texture_id);
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            image.as_ptr() as *const c_void,
+        );
+
+        let wrap: gl::types::GLenum = options.wrap.into();
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap as i32);
+
+        let min_filter = match (options.min_filter, options.mipmaps) {
+            (Filter::Nearest, false) => gl::NEAREST,
+            (Filter::Linear, false)  => gl::LINEAR,
+            (Filter::Nearest, true)  => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true)   => gl::LINEAR_MIPMAP_LINEAR,
+        };
+        let mag_filter = match options.mag_filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear  => gl::LINEAR,
+        };
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
+
+        if options.mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+
+        Texture { texture_id, width, height }
+    }
+}
+
+// Binds a texture to the given texture unit, which is what sampler uniforms refer to.
+// Takes the id rather than a Texture, since that is what scene nodes keep
+pub unsafe fn bind(texture_id: u32, unit: u32) {
+    gl::ActiveTexture(gl::TEXTURE0 + unit);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.texture_id) }
+    }
+}
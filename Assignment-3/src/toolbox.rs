@@ -1,5 +1,7 @@
 extern crate nalgebra_glm as glm;
 
+use crate::scene_graph::EulerOrder;
+
 pub struct Heading {
     pub x: f32,
     pub z: f32,
@@ -8,6 +10,31 @@ pub struct Heading {
     pub roll: f32,
 }
 
+impl Heading {
+    // Yaw is applied last so that pitch and roll stay relative to the direction of travel
+    pub fn orientation(&self) -> glm::Quat {
+        EulerOrder::YXZ.to_quat(&glm::vec3(self.pitch, self.yaw, self.roll))
+    }
+}
+
+// Spherical linear interpolation between two orientations, always taking the shortest way around
+#[allow(dead_code)]
+pub fn slerp(from: &glm::Quat, to: &glm::Quat, t: f32) -> glm::Quat {
+    let from = glm::quat_normalize(from);
+    let mut to = glm::quat_normalize(to);
+    let mut cos_angle = glm::quat_dot(&from, &to);
+    if cos_angle < 0.0 {
+        to = -to;
+        cos_angle = -cos_angle;
+    }
+    // Nearly identical orientations would divide by almost zero below, but a normalized lerp is just as good there
+    if cos_angle > 0.9995 {
+        return glm::quat_normalize(&(from * (1.0 - t) + to * t));
+    }
+    let angle = cos_angle.acos();
+    (from * ((1.0 - t) * angle).sin() + to * (t * angle).sin()) / angle.sin()
+}
+
 pub fn simple_heading_animation(time: f32) -> Heading {
     let t = time as f64;
     let step = 0.05f64;
@@ -34,4 +61,36 @@ pub fn simple_heading_animation(time: f32) -> Heading {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn about_y(angle: f32) -> glm::Quat {
+        glm::quat_angle_axis(angle, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    fn assert_same_rotation(a: &glm::Quat, b: &glm::Quat) {
+        // q and -q are the same rotation
+        assert!(glm::quat_dot(a, b).abs() > 1.0 - 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn slerp_end_points_and_middle() {
+        let (from, to) = (about_y(0.2), about_y(1.4));
+        assert_same_rotation(&slerp(&from, &to, 0.0), &from);
+        assert_same_rotation(&slerp(&from, &to, 1.0), &to);
+        assert_same_rotation(&slerp(&from, &to, 0.5), &about_y(0.8));
+        assert!((glm::quat_length(&slerp(&from, &to, 0.3)) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn slerp_takes_shortest_path() {
+        // 350 degrees one way is 10 degrees the other, so halfway is 5 degrees past the start, not 175
+        let from = about_y(0.0);
+        let to = about_y(350f32.to_radians());
+        assert_same_rotation(&slerp(&from, &to, 0.5), &about_y(-5f32.to_radians()));
+
+        // The same holds when the target is given as the negated quaternion
+        assert_same_rotation(&slerp(&from, &-about_y(0.5), 0.5), &about_y(0.25));
+    }
+}
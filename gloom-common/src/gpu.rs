@@ -0,0 +1,109 @@
+use gl;
+
+use crate::{byte_size_of_array, pointer_to_array};
+
+// Owned OpenGL objects, which are deleted when they are dropped. Like every other OpenGL call, dropping them
+// has to happen on the thread the context is current on
+
+pub struct Buffer {
+    id: u32,
+    target: gl::types::GLenum,
+}
+
+#[allow(dead_code)]
+impl Buffer {
+    /// target is what the buffer is bound as, like gl::ARRAY_BUFFER or gl::ELEMENT_ARRAY_BUFFER
+    ///
+    /// # Safety
+    /// An OpenGL context has to be current on this thread, and stay current until the buffer is dropped
+    pub unsafe fn new(target: gl::types::GLenum) -> Buffer {
+        let mut id: u32 = 0;
+        gl::GenBuffers(1, &mut id);
+        Buffer { id, target }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// # Safety
+    /// The buffer has to belong to the OpenGL context current on this thread
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.target, self.id);
+    }
+
+    /// Binds the buffer and replaces its contents. Empty data leaves the buffer empty
+    ///
+    /// # Safety
+    /// The buffer has to belong to the OpenGL context current on this thread
+    pub unsafe fn upload<T>(&self, data: &[T], usage: gl::types::GLenum) {
+        self.bind();
+        if !data.is_empty() {
+            gl::BufferData(self.target, byte_size_of_array(data), pointer_to_array(data), usage);
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) }
+    }
+}
+
+pub struct VertexArray {
+    id: u32,
+}
+
+impl VertexArray {
+    /// # Safety
+    /// An OpenGL context has to be current on this thread, and stay current until the vertex array is dropped
+    pub unsafe fn new() -> VertexArray {
+        let mut id: u32 = 0;
+        gl::GenVertexArrays(1, &mut id);
+        VertexArray { id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// # Safety
+    /// The vertex array has to belong to the OpenGL context current on this thread
+    pub unsafe fn bind(&self) {
+        gl::BindVertexArray(self.id);
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.id) }
+    }
+}
+
+pub struct Program {
+    id: u32,
+}
+
+impl Program {
+    /// # Safety
+    /// An OpenGL context has to be current on this thread, and stay current until the program is dropped
+    pub unsafe fn new() -> Program {
+        Program { id: gl::CreateProgram() }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// # Safety
+    /// The program has to belong to the OpenGL context current on this thread
+    pub unsafe fn activate(&self) {
+        gl::UseProgram(self.id);
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) }
+    }
+}
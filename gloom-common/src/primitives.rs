@@ -0,0 +1,275 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// Shapes generated in code, for debug scenes, tests, and anything else that would otherwise need the vertices
+// typed out by hand. They are all centered on the origin with y up, and have normals and texture coordinates.
+// Front faces are counter clockwise from the outside
+
+// The vertex data of a shape, three floats per vertex for positions and normals and two for texture coordinates,
+// ready to be given to vertex::VaoBuilder
+#[derive(Default)]
+pub struct Primitive {
+    pub vertices: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub texcoords: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl Primitive {
+    fn vertex(&mut self, position: glm::Vec3, normal: glm::Vec3, texcoord: glm::Vec2) -> u32 {
+        self.vertices.extend_from_slice(&[position.x, position.y, position.z]);
+        self.normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+        self.texcoords.extend_from_slice(&[texcoord.x, texcoord.y]);
+        (self.vertices.len() / 3 - 1) as u32
+    }
+
+    // A surface of columns x rows quads. The function is given u and v from 0 to 1, and returns the position,
+    // normal and texture coordinate there. It must be oriented so that the cross product of the derivative
+    // along u and the derivative along v points out of the surface
+    fn grid<F>(&mut self, columns: u32, rows: u32, surface: F)
+        where F: Fn(f32, f32) -> (glm::Vec3, glm::Vec3, glm::Vec2)
+    {
+        let first = (self.vertices.len() / 3) as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (position, normal, texcoord) = surface(column as f32 / columns as f32, row as f32 / rows as f32);
+                self.vertex(position, normal, texcoord);
+            }
+        }
+        let index = |column: u32, row: u32| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let (a, b, c, d) = (index(column, row), index(column + 1, row), index(column + 1, row + 1), index(column, row + 1));
+                self.indices.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+    }
+}
+
+// Point on the circle of the given radius around the y axis, where angle 0 is on the z axis
+fn around_y(angle: f32, radius: f32, y: f32) -> glm::Vec3 {
+    glm::vec3(radius * angle.sin(), y, radius * angle.cos())
+}
+
+// Flat in the xz plane, facing up
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> Primitive {
+    let mut primitive = Primitive::default();
+    primitive.grid(columns.max(1), rows.max(1), |u, v| (
+        glm::vec3((u - 0.5) * width, 0.0, (0.5 - v) * depth),
+        glm::vec3(0.0, 1.0, 0.0),
+        glm::vec2(u, v),
+    ));
+    primitive
+}
+
+// Every face is divided into segments x segments quads, and has the whole texture
+pub fn cube(size: f32, segments: u32) -> Primitive {
+    let segments = segments.max(1);
+    let (x, y, z) = (glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0));
+    // Normal, and the directions u and v go in on the face
+    let faces = [(x, -z, y), (-x, z, y), (y, x, -z), (-y, x, z), (z, x, y), (-z, -x, y)];
+
+    let mut primitive = Primitive::default();
+    for &(normal, u_axis, v_axis) in faces.iter() {
+        primitive.grid(segments, segments, |u, v| (
+            (normal * 0.5 + u_axis * (u - 0.5) + v_axis * (v - 0.5)) * size,
+            normal,
+            glm::vec2(u, v),
+        ));
+    }
+    primitive
+}
+
+// Texture coordinates go around the equator in u and from the bottom pole to the top pole in v
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Primitive {
+    let mut primitive = Primitive::default();
+    primitive.grid(segments.max(3), rings.max(2), |u, v| {
+        let latitude = v * PI;
+        let normal = around_y(u * 2.0 * PI, latitude.sin(), -latitude.cos());
+        (normal * radius, normal, glm::vec2(u, v))
+    });
+    primitive
+}
+
+// A sphere of evenly sized triangles, from an icosahedron with every triangle split into four, subdivisions times.
+// Texture coordinates are the same as for uv_sphere, but stretch across one column of triangles at the seam
+pub fn icosphere(radius: f32, subdivisions: u32) -> Primitive {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points: Vec<glm::Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z))).collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by two triangles, which must share the new point too
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, points: &mut Vec<glm::Vec3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(glm::normalize(&(points[a as usize] + points[b as usize])));
+                (points.len() - 1) as u32
+            })
+        };
+        triangles = triangles.iter().flat_map(|&[a, b, c]| {
+            let ab = midpoint(a, b, &mut points);
+            let bc = midpoint(b, c, &mut points);
+            let ca = midpoint(c, a, &mut points);
+            vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        }).collect();
+    }
+
+    let mut primitive = Primitive::default();
+    for normal in points {
+        let texcoord = glm::vec2(0.5 + normal.x.atan2(normal.z) / (2.0 * PI), 0.5 + normal.y.asin() / PI);
+        primitive.vertex(normal * radius, normal, texcoord);
+    }
+    primitive.indices = triangles.iter().flat_map(|triangle| triangle.iter().cloned()).collect();
+    primitive
+}
+
+// A flat circle facing up, with the texture laid on top of it
+fn cap(primitive: &mut Primitive, radius: f32, y: f32, segments: u32, up: bool) {
+    let normal = glm::vec3(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+    primitive.grid(segments, 1, |u, v| {
+        // Going from the rim to the center faces up, and from the center to the rim faces down
+        let distance = if up { 1.0 - v } else { v };
+        let position = around_y(u * 2.0 * PI, distance * radius, y);
+        let texcoord = glm::vec2(0.5 + position.x / (2.0 * radius), 0.5 - normal.y * position.z / (2.0 * radius));
+        (position, normal, texcoord)
+    });
+}
+
+// Flat circle in the xz plane, facing up
+pub fn disk(radius: f32, segments: u32) -> Primitive {
+    let mut primitive = Primitive::default();
+    cap(&mut primitive, radius, 0.0, segments.max(3), true);
+    primitive
+}
+
+// Around the y axis, with caps. The side has the texture wrapped around it once
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Primitive {
+    let segments = segments.max(3);
+    let mut primitive = Primitive::default();
+    primitive.grid(segments, 1, |u, v| {
+        let angle = u * 2.0 * PI;
+        (around_y(angle, radius, (v - 0.5) * height), around_y(angle, 1.0, 0.0), glm::vec2(u, v))
+    });
+    cap(&mut primitive, radius, height / 2.0, segments, true);
+    cap(&mut primitive, radius, -height / 2.0, segments, false);
+    primitive
+}
+
+// Pointing up the y axis, with a cap at the bottom
+pub fn cone(radius: f32, height: f32, segments: u32) -> Primitive {
+    let segments = segments.max(3);
+    let mut primitive = Primitive::default();
+    primitive.grid(segments, 1, |u, v| {
+        let angle = u * 2.0 * PI;
+        // The side leans in by radius over height, so the normal leans up by the same amount
+        let normal = glm::normalize(&around_y(angle, height, radius));
+        (around_y(angle, (1.0 - v) * radius, (v - 0.5) * height), normal, glm::vec2(u, v))
+    });
+    cap(&mut primitive, radius, -height / 2.0, segments, false);
+    primitive
+}
+
+// Ring around the y axis. major_radius is from the center to the middle of the tube, minor_radius is the tube's own
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Primitive {
+    let mut primitive = Primitive::default();
+    primitive.grid(major_segments.max(3), minor_segments.max(3), |u, v| {
+        let (angle, tube_angle) = (u * 2.0 * PI, v * 2.0 * PI);
+        let normal = around_y(angle, tube_angle.cos(), tube_angle.sin());
+        (around_y(angle, major_radius, 0.0) + normal * minor_radius, normal, glm::vec2(u, v))
+    });
+    primitive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all() -> Vec<(&'static str, Primitive)> {
+        vec![
+            ("plane", plane(2.0, 1.0, 3, 2)),
+            ("cube", cube(1.0, 2)),
+            ("uv_sphere", uv_sphere(0.5, 12, 6)),
+            ("icosphere", icosphere(0.5, 2)),
+            ("disk", disk(0.5, 12)),
+            ("cylinder", cylinder(0.5, 1.0, 12)),
+            ("cone", cone(0.5, 1.0, 12)),
+            ("torus", torus(0.5, 0.15, 12, 6)),
+        ]
+    }
+
+    fn vec3(values: &[f32], index: u32) -> glm::Vec3 {
+        let i = index as usize * 3;
+        glm::vec3(values[i], values[i + 1], values[i + 2])
+    }
+
+    fn vertex_count(primitive: &Primitive) -> usize {
+        primitive.vertices.len() / 3
+    }
+
+    #[test]
+    fn front_faces_follow_the_normals() {
+        for (name, primitive) in all() {
+            let position = |vertex: u32| vec3(&primitive.vertices, vertex);
+            let normal = |vertex: u32| vec3(&primitive.normals, vertex);
+            for triangle in primitive.indices.chunks_exact(3) {
+                let (a, b, c) = (position(triangle[0]), position(triangle[1]), position(triangle[2]));
+                let face = glm::cross(&(b - a), &(c - a));
+                // The poles of the uv sphere and the tip of the cone have triangles with no area, and no direction
+                if face.norm() < 1e-6 {
+                    continue;
+                }
+                let normals = normal(triangle[0]) + normal(triangle[1]) + normal(triangle[2]);
+                assert!(glm::dot(&face, &normals) > 0.0, "{} has a triangle facing away from its normals", name);
+            }
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_length() {
+        for (name, primitive) in all() {
+            for vertex in 0..vertex_count(&primitive) as u32 {
+                let length = vec3(&primitive.normals, vertex).norm();
+                assert!((length - 1.0).abs() < 1e-5, "{} has a normal of length {}", name, length);
+            }
+        }
+    }
+
+    #[test]
+    fn texcoords_are_within_the_texture() {
+        for (name, primitive) in all() {
+            assert_eq!(primitive.texcoords.len(), vertex_count(&primitive) * 2, "{}", name);
+            assert_eq!(primitive.normals.len(), primitive.vertices.len(), "{}", name);
+            assert!(primitive.texcoords.iter().all(|t| (0.0..=1.0).contains(t)), "{} has texcoords outside [0, 1]", name);
+            assert!(primitive.indices.iter().all(|&i| (i as usize) < vertex_count(&primitive)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn resolution_sets_the_vertex_count() {
+        assert_eq!(vertex_count(&plane(1.0, 1.0, 4, 3)), 5 * 4);
+        assert_eq!(vertex_count(&cube(1.0, 3)), 6 * 4 * 4);
+        assert_eq!(vertex_count(&uv_sphere(1.0, 8, 4)), 9 * 5);
+        assert_eq!(vertex_count(&icosphere(1.0, 0)), 12);
+        assert_eq!(vertex_count(&icosphere(1.0, 1)), 42);
+        assert_eq!(vertex_count(&disk(1.0, 8)), 9 * 2);
+        // The side and two caps
+        assert_eq!(vertex_count(&cylinder(1.0, 1.0, 8)), 3 * 9 * 2);
+        assert_eq!(vertex_count(&cone(1.0, 1.0, 8)), 2 * 9 * 2);
+        assert_eq!(vertex_count(&torus(1.0, 0.5, 8, 4)), 9 * 5);
+        // Too low a resolution is raised to the lowest that makes a shape
+        assert_eq!(vertex_count(&plane(1.0, 1.0, 0, 0)), 4);
+        assert_eq!(vertex_count(&cylinder(1.0, 1.0, 1)), vertex_count(&cylinder(1.0, 1.0, 3)));
+    }
+}
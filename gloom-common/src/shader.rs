@@ -0,0 +1,414 @@
+extern crate nalgebra_glm as glm;
+
+use gl;
+use std::{
+    ptr,
+    str,
+    cell::RefCell,
+    collections::HashMap,
+    ffi::CString,
+    path::Path,
+    time::SystemTime,
+};
+
+use crate::gpu::Program;
+
+// The program is deleted when the shader is dropped
+pub struct Shader {
+    pub program: Program,
+    // What the shader was built from, so it can be built again when the files change.
+    // The files have their modification time from when they were last read
+    files: Vec::<(String, Option<SystemTime>)>,
+    sources: Vec::<(String, ShaderType)>,
+    // Uniform locations by name, looked up the first time they are set. Missing uniforms are cached as -1
+    uniform_locations: RefCell<HashMap<String, i32>>,
+}
+
+// Values that can be uploaded to a uniform of the matching GLSL type
+pub trait Uniform {
+    /// # Safety
+    /// The location has to be a uniform of the program whose GLSL type matches the value, and the program has to
+    /// belong to the OpenGL context current on this thread
+    unsafe fn upload(&self, program_id: u32, location: i32);
+}
+
+impl Uniform for f32 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniform1f(program_id, location, *self);
+    }
+}
+
+// Also used for samplers, with the texture unit, and for bools
+impl Uniform for i32 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniform1i(program_id, location, *self);
+    }
+}
+
+impl Uniform for u32 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniform1ui(program_id, location, *self);
+    }
+}
+
+impl Uniform for glm::Vec2 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniform2fv(program_id, location, 1, self.as_ptr());
+    }
+}
+
+impl Uniform for glm::Vec3 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniform3fv(program_id, location, 1, self.as_ptr());
+    }
+}
+
+impl Uniform for glm::Vec4 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniform4fv(program_id, location, 1, self.as_ptr());
+    }
+}
+
+impl Uniform for glm::Mat3 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniformMatrix3fv(program_id, location, 1, gl::FALSE, self.as_ptr());
+    }
+}
+
+impl Uniform for glm::Mat4 {
+    unsafe fn upload(&self, program_id: u32, location: i32) {
+        gl::ProgramUniformMatrix4fv(program_id, location, 1, gl::FALSE, self.as_ptr());
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl Shader {
+    /// # Safety
+    /// The shader has to belong to the OpenGL context current on this thread
+    pub unsafe fn activate(&self) {
+        self.program.activate();
+    }
+
+    /// The location of the named uniform, or -1 if the program doesn't have it. Uniforms the shaders declare but
+    /// never use are removed by the driver, so they are missing too. Warns the first time a name is missing.
+    /// Names with a nul byte can't be passed to the driver, so they are an error
+    ///
+    /// # Safety
+    /// The shader has to belong to the OpenGL context current on this thread
+    pub unsafe fn uniform_location(&self, name: &str) -> Result<i32, ShaderError> {
+        if let Some(&location) = self.uniform_locations.borrow().get(name) {
+            return Ok(location);
+        }
+        let c_name = CString::new(name).map_err(|_| ShaderError::InvalidUniformName { name: name.to_string() })?;
+        let location = gl::GetUniformLocation(self.program.id(), c_name.as_ptr());
+        if location == -1 {
+            let paths: Vec<&str> = self.files.iter().map(|(path, _)| path.as_str()).collect();
+            println!("Warning: No uniform named {} in shader {}", name, paths.join(", "));
+        }
+        self.uniform_locations.borrow_mut().insert(name.to_string(), location);
+        Ok(location)
+    }
+
+    /// Sets the uniform in this program, whether or not it is the active one. Does nothing if it doesn't have it,
+    /// and only warns if the name is invalid
+    ///
+    /// # Safety
+    /// The shader has to belong to the OpenGL context current on this thread, and the uniform with this name
+    /// has to have the GLSL type matching T
+    pub unsafe fn set_uniform<T: Uniform>(&self, name: &str, value: &T) {
+        match self.uniform_location(name) {
+            Ok(-1) => {}
+            Ok(location) => value.upload(self.program.id(), location),
+            Err(e) => println!("Warning: {}", e),
+        }
+    }
+
+    /// # Safety
+    /// Same as for set_uniform
+    pub unsafe fn set_f32(&self, name: &str, value: f32) {
+        self.set_uniform(name, &value);
+    }
+
+    /// # Safety
+    /// Same as for set_uniform
+    pub unsafe fn set_i32(&self, name: &str, value: i32) {
+        self.set_uniform(name, &value);
+    }
+
+    /// # Safety
+    /// Same as for set_uniform
+    pub unsafe fn set_bool(&self, name: &str, value: bool) {
+        self.set_uniform(name, &(value as i32));
+    }
+
+    /// # Safety
+    /// Same as for set_uniform
+    pub unsafe fn set_vec3(&self, name: &str, value: &glm::Vec3) {
+        self.set_uniform(name, value);
+    }
+
+    /// # Safety
+    /// Same as for set_uniform
+    pub unsafe fn set_vec4(&self, name: &str, value: &glm::Vec4) {
+        self.set_uniform(name, value);
+    }
+
+    /// # Safety
+    /// Same as for set_uniform
+    pub unsafe fn set_mat4(&self, name: &str, value: &glm::Mat4) {
+        self.set_uniform(name, value);
+    }
+
+    /// Builds the shader again if any of its files have been modified since they were last read, and returns whether
+    /// it did. The new program replaces the old one only if it compiles and links, otherwise the old one is kept and
+    /// the error returned. Either way it is not tried again until the files change again.
+    /// The new program is not active, and has none of the uniforms set, so call this before setting them.
+    /// Cached uniform locations are thrown away with the old program
+    ///
+    /// # Safety
+    /// The shader has to belong to the OpenGL context current on this thread
+    pub unsafe fn reload_if_changed(&mut self) -> Result<bool, ShaderError> {
+        if self.files.iter().all(|(path, time)| modified_time(path) == *time) {
+            return Ok(false);
+        }
+
+        let (files, sources) = (&self.files, &self.sources);
+        let rebuild = || {
+            let mut builder = ShaderBuilder::new();
+            for (path, _) in files {
+                builder = builder.attach_file(path)?;
+            }
+            for (source, shader_type) in sources {
+                builder = builder.compile_shader(source, *shader_type)?;
+            }
+            builder.link()
+        };
+
+        match rebuild() {
+            Ok(shader) => {
+                *self = shader;
+                Ok(true)
+            }
+            Err(e) => {
+                // Remember the times anyway, so a broken file isn't compiled again every frame
+                for (path, time) in &mut self.files {
+                    *time = modified_time(path);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+pub struct ShaderBuilder {
+    program: Program,
+    shaders: Vec::<u32>,
+    // What the shaders were compiled from, for link errors and reloading
+    files: Vec::<(String, Option<SystemTime>)>,
+    sources: Vec::<(String, ShaderType)>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum ShaderType {
+    Vertex,
+    Fragment,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+}
+
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
+            ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
+            ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
+            ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
+            ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
+            ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+        }
+    }
+}
+
+impl ShaderType {
+    fn from_ext(ext: &std::ffi::OsStr) -> Option<ShaderType> {
+        match ext.to_str()? {
+            "vert" => { Some(ShaderType::Vertex) },
+            "frag" => { Some(ShaderType::Fragment) },
+            "tcs"  => { Some(ShaderType::TessellationControl) },
+            "tes"  => { Some(ShaderType::TessellationEvaluation) },
+            "geom" => { Some(ShaderType::Geometry) },
+            _ => { None },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Read { path: String, error: std::io::Error },
+    // The stage is picked from the extension, which has to be one of vert, frag, tcs, tes and geom
+    UnknownExtension { path: String },
+    // The path is None for shaders compiled from a string
+    Compile { path: Option<String>, stage: ShaderType, log: String },
+    Link { paths: Vec<String>, log: String },
+    // Sources are handed to the driver as C strings, which can't contain nul bytes. The position is in bytes
+    NulInSource { path: Option<String>, position: usize },
+    // Uniform names have the same restriction
+    InvalidUniformName { name: String },
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShaderError::Read { path, error } => write!(f, "Failed to read shader {}: {}", path, error),
+            ShaderError::UnknownExtension { path } => write!(f, "Unknown shader stage for {}", path),
+            ShaderError::Compile { path, stage, log } => write!(f, "Failed to compile {:?} shader {}:\n{}",
+                stage, path.as_deref().unwrap_or("<source>"), log),
+            ShaderError::Link { paths, log } => write!(f, "Failed to link shaders {}:\n{}", paths.join(", "), log),
+            ShaderError::NulInSource { path, position } => write!(f, "Shader {} has a nul byte at byte {}",
+                path.as_deref().unwrap_or("<source>"), position),
+            ShaderError::InvalidUniformName { name } => write!(f, "Uniform name {:?} has a nul byte", name),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl ShaderBuilder {
+    /// # Safety
+    /// An OpenGL context has to be current on this thread, and stay current until the builder and the shader it
+    /// links are dropped
+    pub unsafe fn new() -> ShaderBuilder {
+        ShaderBuilder {
+            program: Program::new(),
+            shaders: vec![],
+            files: vec![],
+            sources: vec![],
+        }
+    }
+
+    /// # Safety
+    /// The builder has to belong to the OpenGL context current on this thread
+    pub unsafe fn attach_file(mut self, shader_path: &str) -> Result<ShaderBuilder, ShaderError> {
+        let path = Path::new(shader_path);
+        let shader_type = match path.extension().and_then(ShaderType::from_ext) {
+            Some(shader_type) => shader_type,
+            None => return self.fail(ShaderError::UnknownExtension { path: shader_path.to_string() }),
+        };
+        // Read the time first, so changes made while reading are picked up by the next reload
+        let time = modified_time(shader_path);
+        let shader_src = match std::fs::read_to_string(path) {
+            Ok(shader_src) => shader_src,
+            Err(error) => return self.fail(ShaderError::Read { path: shader_path.to_string(), error }),
+        };
+        self.files.push((shader_path.to_string(), time));
+        self.compile(&shader_src, shader_type, Some(shader_path))
+    }
+
+    /// # Safety
+    /// The builder has to belong to the OpenGL context current on this thread
+    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> Result<ShaderBuilder, ShaderError> {
+        self.sources.push((shader_src.to_string(), shader_type));
+        self.compile(shader_src, shader_type, None)
+    }
+
+    unsafe fn compile(mut self, shader_src: &str, shader_type: ShaderType, path: Option<&str>) -> Result<ShaderBuilder, ShaderError> {
+        let c_str_shader = match CString::new(shader_src.as_bytes()) {
+            Ok(c_str_shader) => c_str_shader,
+            Err(error) => return self.fail(ShaderError::NulInSource { path: path.map(str::to_string), position: error.nul_position() }),
+        };
+        let shader = gl::CreateShader(shader_type.into());
+        gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
+        gl::CompileShader(shader);
+
+        if let Some(log) = self.check_shader_errors(shader) {
+            gl::DeleteShader(shader);
+            return self.fail(ShaderError::Compile { path: path.map(str::to_string), stage: shader_type, log });
+        }
+
+        self.shaders.push(shader);
+
+        Ok(self)
+    }
+
+    // Deletes the shaders compiled so far, since the builder is given up. The program is deleted when it is dropped
+    unsafe fn fail(self, error: ShaderError) -> Result<ShaderBuilder, ShaderError> {
+        for &shader in &self.shaders {
+            gl::DeleteShader(shader);
+        }
+        Err(error)
+    }
+
+    // Returns the info log if compilation failed
+    unsafe fn check_shader_errors(&self, shader_id: u32) -> Option<String> {
+        let mut success = i32::from(gl::FALSE);
+        gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
+        if success != i32::from(gl::TRUE) {
+            let mut log_length = 0;
+            gl::GetShaderiv(shader_id, gl::INFO_LOG_LENGTH, &mut log_length);
+            let mut info_log: Vec<u8> = vec![0; log_length.max(1) as usize];
+            let mut written = 0;
+            gl::GetShaderInfoLog(
+                shader_id,
+                info_log.len() as i32,
+                &mut written,
+                info_log.as_mut_ptr() as *mut gl::types::GLchar,
+            );
+            info_log.truncate(written as usize);
+            return Some(String::from_utf8_lossy(&info_log).to_string());
+        }
+        None
+    }
+
+    // Returns the info log if linking failed
+    unsafe fn check_linker_errors(&self) -> Option<String> {
+        let mut success = i32::from(gl::FALSE);
+        gl::GetProgramiv(self.program.id(), gl::LINK_STATUS, &mut success);
+        if success != i32::from(gl::TRUE) {
+            let mut log_length = 0;
+            gl::GetProgramiv(self.program.id(), gl::INFO_LOG_LENGTH, &mut log_length);
+            let mut info_log: Vec<u8> = vec![0; log_length.max(1) as usize];
+            let mut written = 0;
+            gl::GetProgramInfoLog(
+                self.program.id(),
+                info_log.len() as i32,
+                &mut written,
+                info_log.as_mut_ptr() as *mut gl::types::GLchar,
+            );
+            info_log.truncate(written as usize);
+            return Some(String::from_utf8_lossy(&info_log).to_string());
+        }
+        None
+    }
+
+    /// # Safety
+    /// The builder has to belong to the OpenGL context current on this thread
+    pub unsafe fn link(self) -> Result<Shader, ShaderError> {
+        for &shader in &self.shaders {
+            gl::AttachShader(self.program.id(), shader);
+        }
+        gl::LinkProgram(self.program.id());
+
+        let linker_errors = self.check_linker_errors();
+
+        // The program keeps what it needs from the shaders after linking
+        for &shader in &self.shaders {
+            gl::DeleteShader(shader);
+        }
+
+        if let Some(log) = linker_errors {
+            let paths = self.files.into_iter().map(|(path, _)| path).collect();
+            return Err(ShaderError::Link { paths, log });
+        }
+
+        Ok(Shader {
+            program: self.program,
+            files: self.files,
+            sources: self.sources,
+            uniform_locations: RefCell::new(HashMap::new()),
+        })
+    }
+}
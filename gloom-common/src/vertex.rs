@@ -0,0 +1,183 @@
+use gl;
+
+use crate::gpu::{Buffer, VertexArray};
+use crate::offset;
+
+// The kinds of per vertex data a VAO can have. Every kind has a fixed attribute location, so shaders can
+// declare them with layout(location=...) and work with any VAO that has the attributes they use
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attribute {
+    Position,
+    Color,
+    Normal,
+    Texcoord,
+    // The tangent, and the sign of the bitangent in w
+    Tangent,
+    // Four joints per vertex for skinning. Stored as floats like everything else, so shaders have to round them
+    JointIndices,
+    JointWeights,
+}
+
+impl Attribute {
+    pub fn location(self) -> u32 {
+        match self {
+            Attribute::Position     => 0,
+            Attribute::Color        => 1,
+            Attribute::Normal       => 2,
+            Attribute::Texcoord     => 3,
+            Attribute::Tangent      => 4,
+            Attribute::JointIndices => 5,
+            Attribute::JointWeights => 6,
+        }
+    }
+
+    // Number of floats per vertex
+    pub fn components(self) -> u32 {
+        match self {
+            Attribute::Position     => 3,
+            Attribute::Color        => 4,
+            Attribute::Normal       => 3,
+            Attribute::Texcoord     => 2,
+            Attribute::Tangent      => 4,
+            Attribute::JointIndices => 4,
+            Attribute::JointWeights => 4,
+        }
+    }
+}
+
+// Where every attribute is within an interleaved vertex. Offsets and the stride are counted in floats
+#[derive(Clone, Debug, Default)]
+pub struct VertexLayout {
+    attributes: Vec<(Attribute, u32)>,
+    stride: u32,
+}
+
+#[allow(dead_code)]
+impl VertexLayout {
+    pub fn new() -> VertexLayout {
+        VertexLayout::default()
+    }
+
+    // Adds the attribute after the ones already in the layout
+    pub fn with(mut self, attribute: Attribute) -> VertexLayout {
+        assert!(!self.contains(attribute), "{:?} is already in the layout", attribute);
+        self.attributes.push((attribute, self.stride));
+        self.stride += attribute.components();
+        self
+    }
+
+    pub fn contains(&self, attribute: Attribute) -> bool {
+        self.attributes.iter().any(|&(a, _)| a == attribute)
+    }
+
+    pub fn offset_of(&self, attribute: Attribute) -> Option<u32> {
+        self.attributes.iter().find(|&&(a, _)| a == attribute).map(|&(_, offset)| offset)
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = Attribute> + '_ {
+        self.attributes.iter().map(|&(attribute, _)| attribute)
+    }
+
+    /// Points the attributes of the bound VAO into the bound array buffer
+    ///
+    /// # Safety
+    /// A vertex array and an array buffer holding vertices in this layout have to be bound in the OpenGL context
+    /// current on this thread
+    pub unsafe fn apply(&self) {
+        let stride = self.stride as i32 * std::mem::size_of::<f32>() as i32;
+        for &(attribute, attribute_offset) in &self.attributes {
+            gl::VertexAttribPointer(
+                attribute.location(),
+                attribute.components() as i32,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                offset::<f32>(attribute_offset),
+            );
+            gl::EnableVertexAttribArray(attribute.location());
+        }
+    }
+}
+
+// A vertex array object with all its attributes in one buffer. Owns its buffers, so dropping it frees everything
+pub struct Vao {
+    pub vertex_array: VertexArray,
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub index_count: i32,
+    pub layout: VertexLayout,
+}
+
+impl Vao {
+    pub fn vao_id(&self) -> u32 {
+        self.vertex_array.id()
+    }
+
+    /// # Safety
+    /// The VAO has to belong to the OpenGL context current on this thread
+    pub unsafe fn bind(&self) {
+        self.vertex_array.bind();
+    }
+}
+
+// Packs separate attribute arrays into one interleaved buffer. Attributes are given one array each, like
+// the meshes store them, and empty arrays are left out of the layout, so optional attributes can always be passed
+pub struct VaoBuilder<'a> {
+    indices: &'a [u32],
+    attributes: Vec<(Attribute, &'a [f32])>,
+}
+
+impl<'a> VaoBuilder<'a> {
+    pub fn new(indices: &'a [u32]) -> VaoBuilder<'a> {
+        VaoBuilder { indices, attributes: vec![] }
+    }
+
+    pub fn attribute(mut self, attribute: Attribute, values: &'a [f32]) -> VaoBuilder<'a> {
+        if !values.is_empty() {
+            self.attributes.push((attribute, values));
+        }
+        self
+    }
+
+    /// Panics if the attributes don't have the same number of vertices
+    ///
+    /// # Safety
+    /// An OpenGL context has to be current on this thread, and stay current until the VAO is dropped
+    pub unsafe fn build(self) -> Vao {
+        let layout = self.attributes.iter()
+            .fold(VertexLayout::new(), |layout, &(attribute, _)| layout.with(attribute));
+
+        let vertex_count = self.attributes.first()
+            .map_or(0, |&(attribute, values)| values.len() / attribute.components() as usize);
+        for &(attribute, values) in &self.attributes {
+            assert_eq!(values.len(), vertex_count * attribute.components() as usize,
+                "{:?} has a different number of vertices than {:?}", attribute, self.attributes[0].0);
+        }
+
+        let mut interleaved = Vec::with_capacity(vertex_count * layout.stride() as usize);
+        for vertex in 0..vertex_count {
+            for &(attribute, values) in &self.attributes {
+                let components = attribute.components() as usize;
+                interleaved.extend_from_slice(&values[vertex * components..(vertex + 1) * components]);
+            }
+        }
+
+        let vertex_array = VertexArray::new();
+        vertex_array.bind();
+
+        let vertex_buffer = Buffer::new(gl::ARRAY_BUFFER);
+        vertex_buffer.upload(&interleaved, gl::STATIC_DRAW);
+        layout.apply();
+
+        // The element array binding is part of the VAO state, so this has to happen while it is bound
+        let index_buffer = Buffer::new(gl::ELEMENT_ARRAY_BUFFER);
+        index_buffer.upload(self.indices, gl::STATIC_DRAW);
+
+        Vao { vertex_array, vertex_buffer, index_buffer, index_count: self.indices.len() as i32, layout }
+    }
+}